@@ -0,0 +1,148 @@
+use std::{
+    cell::RefCell,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    config, diagnostics, link, resolver,
+    util::{content_hash, find_file, vault_root},
+};
+
+pub fn is_image(name: &str) -> bool {
+    let name = name.split('|').next().unwrap_or_default().trim();
+    let extension = Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    matches!(
+        extension.as_deref(),
+        Some("png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "bmp" | "avif")
+    )
+}
+
+// 画像の参照名から出力するURLを求める
+// 見つからない場合は警告を出し、従来通りプレフィックスをつけただけのURLを返す
+// vaultが指定されていなければ探しようがないので警告は出さない
+pub fn picture_url(name: &str) -> String {
+    match resolver::get().resolve_asset(name) {
+        Some(url) => url,
+        None => {
+            if vault_root().is_some() {
+                diagnostics::warn(format!("image `{name}` was not found in the vault"));
+            }
+            format!("{}{name}", config::get().asset_prefix)
+        }
+    }
+}
+
+thread_local! {
+    // 変換しているノートの出力先のディレクトリ。buildの間だけ設定される
+    static OUTPUT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+// ノートをoutputに書き出す変換の間、参照された画像も出力先にコピーさせる
+pub fn with_output<T>(output: &Path, f: impl FnOnce() -> T) -> T {
+    let previous = OUTPUT.with(|o| o.replace(Some(output.to_path_buf())));
+    let result = f();
+    OUTPUT.with(|o| *o.borrow_mut() = previous);
+    result
+}
+
+// 画像のコピー先。asset_outputが指定されていればそこに、buildではasset_prefixのパスに対応する出力先のディレクトリにする
+fn asset_destination() -> Option<PathBuf> {
+    let config = config::get();
+    if !config.copy_assets {
+        return None;
+    }
+    if let Some(output) = &config.asset_output {
+        return Some(output.clone());
+    }
+    let output = OUTPUT.with(|o| o.borrow().clone())?;
+    let path = link::url_path(&config.asset_prefix).trim_matches('/');
+    if path.is_empty() {
+        Some(output)
+    } else {
+        is_relative_name(path).then(|| output.join(path))
+    }
+}
+
+// vault内の画像のURL。コピー先があればコピーも行う
+pub fn vault_asset_url(name: &str) -> Option<String> {
+    let prefix = &config::get().asset_prefix;
+    let path = find_asset(name)?;
+    let url = match asset_destination() {
+        Some(output) => match copy_asset(&path, &output) {
+            Ok(file_name) => format!("{prefix}{file_name}"),
+            Err(e) => {
+                diagnostics::warn(format!("cannot copy `{}`: {e}", path.display()));
+                format!("{prefix}{name}")
+            }
+        },
+        None => format!("{prefix}{name}"),
+    };
+    Some(url)
+}
+
+// Obsidianと同様に、添付ファイルフォルダが設定されていればそこを優先し、なければvault全体から探す
+// ..や絶対パスを含む名前はvaultの外を指しうるので探さない
+pub fn find_asset(name: &str) -> Option<PathBuf> {
+    if !is_relative_name(name) {
+        return None;
+    }
+    if let (Some(folder), Some(root)) = (&config::get().attachment_folder, vault_root()) {
+        let path = root.join(folder).join(name);
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+    find_file(name)
+}
+
+// 通常の名前だけからなる相対パス
+pub fn is_relative_name(name: &str) -> bool {
+    !name.is_empty()
+        && Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+// 内容のハッシュをファイル名に含めてコピーし、コピー先のファイル名を返す
+fn copy_asset(path: &Path, output: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)?;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let hash = content_hash(&bytes);
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}.{hash}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{hash}"),
+    };
+
+    let destination = output.join(&file_name);
+    if !destination.exists() {
+        fs::create_dir_all(output)?;
+        fs::write(&destination, bytes)?;
+    }
+    Ok(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn copies_assets_with_hashed_names() {
+        let vault = testing::init();
+        let source = vault.join("attachments/pic.png");
+        assert_eq!(find_asset("pic.png"), Some(source.clone()));
+
+        let output = testing::output_dir("hashed-assets");
+        let file_name = copy_asset(&source, &output).unwrap();
+        let hash = content_hash(&fs::read(&source).unwrap());
+        assert_eq!(file_name, format!("pic.{hash}.png"));
+        assert!(output.join(&file_name).is_file());
+        let _ = fs::remove_dir_all(output);
+    }
+}
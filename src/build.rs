@@ -0,0 +1,263 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use crate::{
+    asset, config, embed,
+    graph::{self, LinkGraph},
+    lexer::block_lexer::BlockLexer,
+    metadata::{tag_url, Metadata},
+    render::{html, text},
+    resolver,
+    search::SearchIndex,
+    tags::TagIndex,
+    template,
+    token::BlockToken,
+    util::{escape_html, vault_root},
+};
+
+pub struct ParsedNote {
+    pub tokens: Vec<BlockToken>,
+    pub metadata: Metadata,
+}
+
+pub struct RenderedNote {
+    pub html: String,
+    pub tokens: Vec<BlockToken>,
+    pub metadata: Metadata,
+}
+
+pub fn parse_note(path: &Path, content: &str) -> ParsedNote {
+    let mut block_lexer = BlockLexer::from_source(content);
+    let tokens = embed::with_root(path, || block_lexer.tokenize());
+    ParsedNote {
+        tokens,
+        metadata: block_lexer.metadata(),
+    }
+}
+
+// 1つのノートをHTMLに変換する。テンプレートが設定されていればそれに埋め込む
+// graphが与えられた場合はバックリンクも出力する
+pub fn render_note(path: &Path, content: &str, graph: Option<&LinkGraph>) -> RenderedNote {
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let ParsedNote { tokens, metadata } = parse_note(path, content);
+    let html = html::to_html(&tokens);
+
+    let Some(page) = template::get() else {
+        return RenderedNote {
+            html,
+            tokens,
+            metadata,
+        };
+    };
+    let backlinks = graph
+        .map(|graph| backlinks_html(graph, &graph::note_id(path)))
+        .unwrap_or_default();
+    let description = text::summary(&tokens, config::get().summary_length);
+    let html = template::render(
+        page,
+        &[
+            (
+                "title",
+                escape_html(metadata.title.as_ref().unwrap_or(&name)),
+            ),
+            ("content", html),
+            ("backlinks", backlinks),
+            ("tags", tags_html(&metadata)),
+            ("tasks_open", metadata.tasks_open.to_string()),
+            ("tasks_closed", metadata.tasks_closed.to_string()),
+            ("description", escape_html(&description)),
+        ],
+    );
+    RenderedNote {
+        html,
+        tokens,
+        metadata,
+    }
+}
+
+fn tags_html(metadata: &Metadata) -> String {
+    if metadata.tags.is_empty() {
+        return String::new();
+    }
+    let items = metadata
+        .tags
+        .iter()
+        .map(|tag| {
+            format!(
+                "<li><a class=\"tag\" href=\"{}\">#{}</a></li>",
+                escape_html(&tag_url(tag)),
+                escape_html(tag)
+            )
+        })
+        .join("\n");
+    format!("<ul class=\"tags\">\n{items}\n</ul>")
+}
+
+fn backlinks_html(graph: &LinkGraph, id: &str) -> String {
+    let backlinks = graph.backlinks(id);
+    if backlinks.is_empty() {
+        return String::new();
+    }
+
+    // 同名のノートがある場合は区別できるようにvaultからの相対パスを表示する
+    let items = backlinks
+        .iter()
+        .map(|source| {
+            let name = graph::note_name(source);
+            let label = if graph.is_ambiguous(name) {
+                source
+            } else {
+                name
+            };
+            match graph.path(source) {
+                Some(path) => format!(
+                    "<li><a href=\"{}\">{}</a></li>",
+                    escape_html(&note_url(source, path)),
+                    escape_html(label)
+                ),
+                None => format!("<li>{}</li>", escape_html(label)),
+            }
+        })
+        .join("\n");
+    format!("<section class=\"backlinks\">\n<h2>Linked from</h2>\n<ul>\n{items}\n</ul>\n</section>")
+}
+
+// vault内のノートをvaultと同じディレクトリ構成でoutputに書き出す
+// リンクの関係はgraph.jsonとgraph.dotとして、タグごとのノートの一覧はtags/以下に、検索用のインデックスはsearch.jsonとして出力する
+pub fn build_vault(output: &Path) -> io::Result<()> {
+    let graph = LinkGraph::from_vault();
+    let mut notes = vec![];
+    for (_, path) in graph.notes() {
+        notes.push(build_note(output, path, &graph)?);
+    }
+    write_indexes(output, &graph, &notes)
+}
+
+// 書き出したノートの情報。タグのページと検索用のインデックスを作るのに使う
+pub struct BuiltNote {
+    pub title: String,
+    pub url: String,
+    pub tokens: Vec<BlockToken>,
+    pub metadata: Metadata,
+}
+
+// 1つのノートを変換してoutputに書き出す
+pub fn build_note(output: &Path, path: &Path, graph: &LinkGraph) -> io::Result<BuiltNote> {
+    let content = fs::read_to_string(path)?;
+    let note = asset::with_output(output, || render_note(path, &content, Some(graph)));
+    write_file(&output_path(output, path), &note.html)?;
+    let id = graph::note_id(path);
+    Ok(BuiltNote {
+        title: note
+            .metadata
+            .title
+            .clone()
+            .unwrap_or(graph::note_name(&id).to_string()),
+        url: note_url(&id, path),
+        tokens: note.tokens,
+        metadata: note.metadata,
+    })
+}
+
+// ノートの一覧から作るファイル(graph.json、graph.dot、search.json、タグのページ)を書き出す
+pub fn write_indexes<'a>(
+    output: &Path,
+    graph: &LinkGraph,
+    notes: impl IntoIterator<Item = &'a BuiltNote>,
+) -> io::Result<()> {
+    let mut tag_index = TagIndex::default();
+    let mut search_index = SearchIndex::default();
+    for note in notes {
+        tag_index.add(&note.title, &note.url, &note.metadata.tags);
+        search_index.add(&note.title, &note.url, &note.metadata.tags, &note.tokens);
+    }
+    write_file(&output.join("graph.json"), &graph.to_json())?;
+    write_file(&output.join("graph.dot"), &graph.to_dot())?;
+    write_file(&output.join("search.json"), &search_index.to_json())?;
+    tag_index.write_pages(output)?;
+    Ok(())
+}
+
+// vault内のノートに対応する出力先のパス
+pub fn output_path(output: &Path, note: &Path) -> PathBuf {
+    output.join(relative_path(note)).with_extension("html")
+}
+
+fn relative_path(note: &Path) -> &Path {
+    vault_root()
+        .and_then(|root| note.strip_prefix(root).ok())
+        .unwrap_or(note)
+}
+
+// ノートの公開先のURL。解決できない場合は出力先のディレクトリをルートとしたパスにする
+// 同名のノートと取り違えないように、ファイル名ではなくID(vaultからの相対パス)で解決する
+pub fn note_url(id: &str, path: &Path) -> String {
+    match resolver::get().resolve_note(id) {
+        Some(link) => link.url,
+        None => format!(
+            "/{}",
+            relative_path(path)
+                .with_extension("html")
+                .to_string_lossy()
+                .replace('\\', "/")
+        ),
+    }
+}
+
+pub fn write_file(path: &Path, content: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn builds_notes_with_the_same_name() {
+        testing::init();
+        let output = testing::output_dir("same-name");
+        build_vault(&output).unwrap();
+        assert!(output.join("a/README.html").is_file());
+        assert!(output.join("b/README.html").is_file());
+        let search = fs::read_to_string(output.join("search.json")).unwrap();
+        assert!(search.contains("\"/a/README.html\"") && search.contains("\"/b/README.html\""));
+        let _ = fs::remove_dir_all(output);
+    }
+
+    #[test]
+    fn copies_referenced_assets_into_the_output() {
+        testing::init();
+        let output = testing::output_dir("assets");
+        build_vault(&output).unwrap();
+        let copied = fs::read_dir(output.join("assets/pictures"))
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect_vec();
+        assert_eq!(copied.len(), 1);
+        assert!(copied[0].starts_with("pic.") && copied[0].ends_with(".png"));
+        let html = fs::read_to_string(output.join("Linked.html")).unwrap();
+        assert!(html.contains(&format!("src=\"/assets/pictures/{}\"", copied[0])));
+        let _ = fs::remove_dir_all(output);
+    }
+
+    #[test]
+    fn links_backlinks_without_url_headers() {
+        testing::init();
+        let graph = LinkGraph::from_vault();
+        assert!(backlinks_html(&graph, "Linked")
+            .contains("<li><a href=\"/a/README.html\">a/README</a></li>"));
+    }
+}
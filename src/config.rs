@@ -0,0 +1,183 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::link::LinkRewrite;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MathRenderer {
+    MathJax, // \( \) / \[ \] で囲んでクライアント側に任せる
+    MathML,  // ビルド時にMathMLへ変換する
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub math: MathRenderer,
+    pub asset_prefix: String,              // 画像のURLの前につけるパス
+    pub attachment_folder: Option<String>, // Obsidianの添付ファイルフォルダ(vaultからの相対パス)
+    pub asset_output: Option<PathBuf>,     // 指定された場合は参照された画像をここへコピーする
+    pub copy_assets: bool, // 画像をコピーするか。buildではasset_outputがなくても出力先にコピーする
+    pub max_embed_depth: usize, // ![[Note]]の埋め込みを展開する深さの上限
+    pub template: Option<PathBuf>, // ページのテンプレート
+    pub site_url: Option<String>, // このホストへのリンクは内部リンクとして扱う
+    pub external_link_new_tab: bool, // 外部リンクに target="_blank" rel="noopener" をつける
+    pub internal_link_class: Option<String>,
+    pub external_link_class: Option<String>,
+    pub link_rewrites: Vec<LinkRewrite>, // 相対リンクに順番に適用する
+    pub tag_url: String,                 // タグのページのURL。{tag}がタグに置き換えられる
+    pub summary_length: usize,           // テンプレートの{{description}}の最大の文字数
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            math: MathRenderer::MathJax,
+            asset_prefix: "/assets/pictures/".to_string(),
+            attachment_folder: None,
+            asset_output: None,
+            copy_assets: true,
+            max_embed_depth: 4,
+            template: None,
+            site_url: None,
+            external_link_new_tab: false,
+            internal_link_class: None,
+            external_link_class: None,
+            link_rewrites: Vec::new(),
+            tag_url: "/tags/{tag}/".to_string(),
+            summary_length: 120,
+        }
+    }
+}
+
+impl Config {
+    // 設定できるキーの一覧。環境変数では ARMP_<KEYを大文字にしたもの> で指定する
+    const KEYS: &'static [&'static str] = &[
+        "math",
+        "asset_prefix",
+        "attachment_folder",
+        "asset_output",
+        "copy_assets",
+        "max_embed_depth",
+        "template",
+        "site_url",
+        "external_link_new_tab",
+        "internal_link_class",
+        "external_link_class",
+        "link_rewrite",
+        "link_base_url",
+        "tag_url",
+        "summary_length",
+    ];
+
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        for key in Self::KEYS {
+            let name = format!("ARMP_{}", key.to_uppercase());
+            if let Ok(value) = env::var(&name) {
+                if let Err(e) = config.set(key, &value) {
+                    eprintln!("Warning: {name}: {e}");
+                }
+            }
+        }
+        config
+    }
+
+    // key = value の行からなる設定ファイルを読む。#で始まる行と空行は無視する
+    // 環境変数の設定の上に読み込むので、両方で指定されたキーはファイルの値になる
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+        let mut config = Self::from_env();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let location = format!("{}:{}", path.display(), i + 1);
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(format!("{location}: expected `key = value`"))?;
+            config
+                .set(key.trim(), value)
+                .map_err(|e| format!("{location}: {e}"))?;
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "math" => {
+                self.math = match value.trim() {
+                    "mathjax" => MathRenderer::MathJax,
+                    "mathml" => MathRenderer::MathML,
+                    other => return Err(format!("unknown math renderer `{other}`")),
+                };
+            }
+            "asset_prefix" => {
+                let prefix = value.trim();
+                self.asset_prefix = if prefix.ends_with('/') {
+                    prefix.to_string()
+                } else {
+                    format!("{prefix}/")
+                };
+            }
+            "attachment_folder" => {
+                self.attachment_folder = Some(value.trim().trim_matches('/').to_string());
+            }
+            "asset_output" => self.asset_output = Some(PathBuf::from(value.trim())),
+            "copy_assets" => self.copy_assets = parse_bool(value)?,
+            "max_embed_depth" => {
+                self.max_embed_depth = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid number `{value}`"))?;
+            }
+            "template" => self.template = Some(PathBuf::from(value.trim())),
+            "site_url" => self.site_url = Some(value.trim().to_string()),
+            "external_link_new_tab" => self.external_link_new_tab = parse_bool(value)?,
+            "internal_link_class" => self.internal_link_class = Some(value.trim().to_string()),
+            "external_link_class" => self.external_link_class = Some(value.trim().to_string()),
+            "link_rewrite" => {
+                // ; 区切りで複数指定できる e.g. ".md=>.html;/index.html=>/"
+                for rule in value.split(';').filter(|rule| !rule.trim().is_empty()) {
+                    self.link_rewrites
+                        .push(LinkRewrite::parse_suffix_rule(rule)?);
+                }
+            }
+            "link_base_url" => {
+                self.link_rewrites
+                    .push(LinkRewrite::Prefix(value.trim().to_string()));
+            }
+            "tag_url" => self.tag_url = value.trim().to_string(),
+            "summary_length" => {
+                self.summary_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid number `{value}`"))?;
+            }
+            _ => return Err(format!("unknown config key `{key}`")),
+        }
+        Ok(())
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.trim() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        other => Err(format!("invalid boolean `{other}`")),
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// 最初にget()が呼ばれるより前に呼ぶ必要がある。既に決まっている場合は渡されたものを返す
+pub fn init(config: Config) -> Result<(), Box<Config>> {
+    CONFIG.set(config).map_err(Box::new)
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::from_env)
+}
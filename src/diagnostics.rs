@@ -0,0 +1,65 @@
+use std::cell::{Cell, RefCell};
+
+use crate::token::Span;
+
+// 変換中に見つかった問題。spanは変換しているノートの中の位置で、埋め込んだノートの中の問題にはつけない
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+thread_local! {
+    // collectで集めている途中の警告。collectが入れ子になった場合は一番内側に入れる
+    static COLLECTORS: RefCell<Vec<Vec<Diagnostic>>> = const { RefCell::new(Vec::new()) };
+    // trueの間は警告を表示しない
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+// 警告を標準エラー出力に表示し、collectの中であれば記録する
+pub fn warn(message: String) {
+    report(Diagnostic {
+        message,
+        span: None,
+    });
+}
+
+pub fn warn_at(span: Span, message: String) {
+    report(Diagnostic {
+        message,
+        span: Some(span),
+    });
+}
+
+fn report(diagnostic: Diagnostic) {
+    if !QUIET.with(|quiet| quiet.get()) {
+        eprintln!("Warning: {}", diagnostic.message);
+    }
+    record(diagnostic);
+}
+
+// 表示はせずに記録だけする
+pub fn record(diagnostic: Diagnostic) {
+    COLLECTORS.with(|collectors| {
+        if let Some(diagnostics) = collectors.borrow_mut().last_mut() {
+            diagnostics.push(diagnostic);
+        }
+    });
+}
+
+// fの実行中に出た警告を集める
+pub fn collect<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    COLLECTORS.with(|collectors| collectors.borrow_mut().push(Vec::new()));
+    let result = f();
+    let diagnostics =
+        COLLECTORS.with(|collectors| collectors.borrow_mut().pop().unwrap_or_default());
+    (result, diagnostics)
+}
+
+// fの実行中に出た警告を表示せずに捨てる。同じノートを後で変換する場合など、警告が重複する場合に使う
+pub fn suppress<T>(f: impl FnOnce() -> T) -> T {
+    let previous = QUIET.with(|quiet| quiet.replace(true));
+    let (result, _) = collect(f);
+    QUIET.with(|quiet| quiet.set(previous));
+    result
+}
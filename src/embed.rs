@@ -0,0 +1,109 @@
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config,
+    diagnostics::{self, Diagnostic},
+    lexer::block_lexer::BlockLexer,
+    token::BlockToken,
+    util::get_path,
+};
+
+thread_local! {
+    // 展開中のノートのパス。循環している埋め込みの検出に使う
+    static EMBED_STACK: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+// 変換対象のノート自身を登録した状態でfを実行し、自分自身の埋め込みも循環として扱う
+pub fn with_root<T>(path: &Path, f: impl FnOnce() -> T) -> T {
+    let path = path.canonicalize().unwrap_or(path.to_path_buf());
+    EMBED_STACK.with(|stack| stack.borrow_mut().push(path));
+    let result = f();
+    EMBED_STACK.with(|stack| stack.borrow_mut().pop());
+    result
+}
+
+// ![[Note]] や ![[Note#Section]] の中身をパースして返す
+pub fn embed(target: &str) -> Result<Vec<BlockToken>, String> {
+    let target = target.split('|').next().unwrap_or_default().trim();
+    let (note, section) = match target.split_once('#') {
+        Some((note, section)) => (note.trim(), Some(section.trim())),
+        None => (target, None),
+    };
+
+    let path = get_path(note.to_string()).ok_or(format!("note `{note}` was not found"))?;
+    let path = path.canonicalize().unwrap_or(path);
+    let depth = EMBED_STACK.with(|stack| stack.borrow().len());
+    if EMBED_STACK.with(|stack| stack.borrow().contains(&path)) {
+        return Err(format!("cyclic embed of `{note}`"));
+    }
+    if depth > config::get().max_embed_depth {
+        return Err(format!(
+            "embed depth exceeds {}",
+            config::get().max_embed_depth
+        ));
+    }
+
+    let content = fs::read_to_string(&path).map_err(|e| format!("cannot read `{note}`: {e}"))?;
+    let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+    if let Some(section) = section {
+        lines = extract_section(&lines, section)
+            .ok_or(format!("section `{section}` was not found in `{note}`"))?;
+    }
+
+    EMBED_STACK.with(|stack| stack.borrow_mut().push(path));
+    let (tokens, nested) = diagnostics::collect(|| BlockLexer::new(lines).tokenize());
+    EMBED_STACK.with(|stack| stack.borrow_mut().pop());
+    // 埋め込んだノートの中の位置は埋め込む側のspanとは対応しないので除く
+    for diagnostic in nested {
+        diagnostics::record(Diagnostic {
+            span: None,
+            ..diagnostic
+        });
+    }
+    Ok(tokens)
+}
+
+// 見出しから、同じかより上のレベルの次の見出しの手前までを取り出す
+fn extract_section(lines: &[String], section: &str) -> Option<Vec<String>> {
+    let heading_level = |line: &str| {
+        let level = line.chars().take_while(|&c| c == '#').count();
+        (level > 0 && line[level..].starts_with(' ')).then_some(level)
+    };
+
+    let start = lines.iter().position(|line| {
+        heading_level(line).is_some_and(|level| line[level..].trim() == section)
+    })?;
+    let level = heading_level(&lines[start]).unwrap();
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| heading_level(line).is_some_and(|l| l <= level))
+        .map_or(lines.len(), |i| start + 1 + i);
+    Some(lines[start..end].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render::html, testing};
+
+    #[test]
+    fn embeds_notes_and_detects_cycles() {
+        let vault = testing::init();
+        let html = html::to_html(&embed("Linked#Linked").unwrap());
+        assert!(html.contains("body of linked"));
+        assert_eq!(
+            embed("Missing").unwrap_err(),
+            "note `Missing` was not found"
+        );
+        assert_eq!(
+            embed("Linked#Nothing").unwrap_err(),
+            "section `Nothing` was not found in `Linked`"
+        );
+        let cyclic = with_root(&vault.join("Linked.md"), || embed("Linked#Linked"));
+        assert_eq!(cyclic.unwrap_err(), "cyclic embed of `Linked`");
+    }
+}
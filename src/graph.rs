@@ -0,0 +1,233 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use crate::{
+    asset, build, diagnostics, resolver,
+    token::Inline,
+    util::{get_path, json_string, vault_notes, vault_root},
+};
+
+// vault内のノート同士の[[リンク]]の関係
+// ノートはvaultからの相対パス(拡張子なし)で区別する。別のフォルダにある同名のノートも別のノートになる
+pub struct LinkGraph {
+    notes: BTreeMap<String, PathBuf>,
+    links: BTreeMap<String, BTreeSet<String>>, // リンク元 -> リンク先
+}
+
+impl LinkGraph {
+    pub fn from_vault() -> Self {
+        let notes: BTreeMap<String, PathBuf> = vault_notes()
+            .into_iter()
+            .map(|path| (note_id(&path), path))
+            .collect();
+        let mut graph = Self {
+            notes,
+            links: BTreeMap::new(),
+        };
+        graph.warn_ambiguous_names();
+
+        for (id, path) in graph.notes.iter() {
+            let Ok(content) = fs::read_to_string(path) else {
+                continue;
+            };
+            // vault内に存在するノートへのリンクだけを辺とする
+            let targets: BTreeSet<String> = extract_links(path, &content)
+                .iter()
+                .filter_map(|target| graph.resolve(target))
+                .filter(|target| *target != id)
+                .cloned()
+                .collect();
+            graph.links.insert(id.clone(), targets);
+        }
+        graph
+    }
+
+    pub fn notes(&self) -> impl Iterator<Item = (&String, &PathBuf)> {
+        self.notes.iter()
+    }
+
+    // [[Name]]や[[folder/Name]]のリンク先のノート。パスの末尾が一致するものを探す
+    // 同名のノートが複数ある場合は、リンクの解決(get_path)と同じものを選ぶ
+    pub fn resolve(&self, name: &str) -> Option<&String> {
+        let suffix = format!("/{name}");
+        let candidates = self
+            .notes
+            .keys()
+            .filter(|id| *id == name || id.ends_with(&suffix))
+            .collect_vec();
+        match candidates[..] {
+            [] => None,
+            [id] => Some(id),
+            _ => {
+                let path = get_path(name.to_string())?;
+                self.notes
+                    .iter()
+                    .find(|(_, p)| **p == path)
+                    .map(|(id, _)| id)
+            }
+        }
+    }
+
+    // ファイル名だけでは区別できないノートを知らせる
+    fn warn_ambiguous_names(&self) {
+        let by_name = self.notes.keys().into_group_map_by(|id| note_name(id));
+        for (name, ids) in by_name.iter().sorted() {
+            if ids.len() > 1 {
+                diagnostics::warn(format!(
+                    "note name `{name}` is ambiguous: {}; [[{name}]] links to only one of them",
+                    ids.iter().join(", ")
+                ));
+            }
+        }
+    }
+
+    // 同じファイル名のノートがほかにもあるか
+    pub fn is_ambiguous(&self, name: &str) -> bool {
+        self.notes.keys().filter(|id| note_name(id) == name).count() > 1
+    }
+
+    pub fn path(&self, id: &str) -> Option<&PathBuf> {
+        self.notes.get(id)
+    }
+
+    pub fn links_from(&self, name: &str) -> impl Iterator<Item = &String> {
+        self.links.get(name).into_iter().flatten()
+    }
+
+    // nameにリンクしているノートの一覧
+    pub fn backlinks(&self, name: &str) -> Vec<&String> {
+        self.links
+            .iter()
+            .filter(|(_, targets)| targets.contains(name))
+            .map(|(source, _)| source)
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        let nodes = self
+            .notes
+            .keys()
+            .map(|name| {
+                let url = resolver::get()
+                    .resolve_note(name)
+                    .map_or("null".to_string(), |link| json_string(&link.url));
+                format!("    {{\"id\": {}, \"url\": {url}}}", json_string(name))
+            })
+            .join(",\n");
+        let edges = self
+            .links
+            .iter()
+            .flat_map(|(source, targets)| targets.iter().map(move |target| (source, target)))
+            .map(|(source, target)| {
+                format!(
+                    "    {{\"source\": {}, \"target\": {}}}",
+                    json_string(source),
+                    json_string(target)
+                )
+            })
+            .join(",\n");
+        format!("{{\n  \"nodes\": [\n{nodes}\n  ],\n  \"edges\": [\n{edges}\n  ]\n}}\n")
+    }
+
+    pub fn to_dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph vault {\n");
+        for name in self.notes.keys() {
+            dot.push_str(&format!("    {};\n", quote(name)));
+        }
+        for (source, targets) in self.links.iter() {
+            for target in targets {
+                dot.push_str(&format!("    {} -> {};\n", quote(source), quote(target)));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// ノートのID。vaultからの相対パスから拡張子を除き、区切りを/にしたもの
+pub fn note_id(path: &Path) -> String {
+    vault_root()
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path)
+        .with_extension("")
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+// IDのうちファイル名の部分。[[Name]]で書かれる名前
+pub fn note_name(id: &str) -> &str {
+    id.rsplit('/').next().unwrap_or(id)
+}
+
+// 本文中の[[Note]]や![[Note]]のリンク先のノート名を取り出す
+// パースした結果から集めるので、コードやコメント、フロントマターの中のもの、エスケープされたものは含まない
+pub fn extract_links(path: &Path, content: &str) -> Vec<String> {
+    let note = diagnostics::suppress(|| resolver::detached(|| build::parse_note(path, content)));
+    let mut links = vec![];
+    for block in note.tokens.iter() {
+        block.walk_inlines(&mut |inline| {
+            if let Inline::WikiLink { note, .. } = &inline.kind {
+                if !note.is_empty() && !asset::is_image(note) {
+                    links.push(note.clone());
+                }
+            }
+        });
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn extracts_links_outside_code() {
+        testing::init();
+        let content = "[[A]] `[[B]]` ![[C#Section|alias]] ![[pic.png]]\n```\n[[D]]\n```\n";
+        let links = extract_links(Path::new("Note.md"), content);
+        assert_eq!(links, ["A", "C"]);
+    }
+
+    #[test]
+    fn collects_backlinks_from_the_vault() {
+        testing::init();
+        let graph = LinkGraph::from_vault();
+        assert_eq!(graph.backlinks("Linked"), ["a/README"]);
+        assert!(graph.to_dot().contains("\"a/README\" -> \"Linked\";"));
+        assert!(graph
+            .to_json()
+            .contains("{\"id\": \"Linked\", \"url\": \"https://example.com/linked\"}"));
+    }
+
+    #[test]
+    fn keeps_notes_with_the_same_name_apart() {
+        testing::init();
+        let graph = LinkGraph::from_vault();
+        let ids = graph.notes().map(|(id, _)| id.as_str()).collect_vec();
+        assert!(ids.contains(&"a/README") && ids.contains(&"b/README"));
+        assert_eq!(
+            graph.resolve("b/README").map(|s| s.as_str()),
+            Some("b/README")
+        );
+        assert!(graph.resolve("README").is_some());
+        assert_eq!(graph.links_from("a/README").collect_vec(), ["Linked"]);
+        assert_eq!(graph.backlinks("Linked"), ["a/README"]);
+    }
+
+    #[test]
+    fn extracts_links_from_the_parsed_note() {
+        testing::init();
+        let content = "---\nrelated: \"[[Front]]\"\n---\n<!-- [[Comment]] -->\n[[Plain|alias]] `[[Inline]]` \\[[Escaped]]\n```\n[[Fenced]]\n```\n![[Embedded#Section]]\n- [ ] [[Task]]\n";
+        assert_eq!(
+            extract_links(Path::new("Note.md"), content),
+            ["Plain", "Embedded", "Task"]
+        );
+    }
+}
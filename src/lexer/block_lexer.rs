@@ -1,151 +1,298 @@
-use std::ops::{Bound, RangeBounds};
+use std::{collections::HashMap, ops::Range, rc::Rc};
 
 use itertools::Itertools;
 
-use crate::token::{BlockToken, BlockType};
+use crate::{
+    asset, diagnostics, embed,
+    lexer::inline_lexer::{normalize_label, InlineLexer, LinkDefinition, LinkDefinitions},
+    metadata::{self, Metadata},
+    resolver,
+    token::{Block, BlockToken, Inline, InlineToken, Position, Span, TaskItem},
+};
 
 pub struct BlockLexer {
     tokens: Vec<BlockToken>,
     index: usize,
     content: Vec<String>,
+    lines: Vec<usize>, // content[i]が元の何行目(0始まり)か。フロントマターなどを取り除いてもspanがずれないようにする
+    line_offsets: Vec<usize>, // 元の各行の先頭のバイト位置
+    definitions: Rc<LinkDefinitions>, // [ref]: url "title" によるリンクの定義
+    metadata: Metadata,
 }
 
 impl BlockLexer {
+    // contentは改行で区切られた各行。spanのバイト位置は行を\nでつないだものを基準にする
+    // 元のテキストがある場合は、実際の改行を基準にするfrom_sourceを使う
     pub fn new(content: Vec<String>) -> Self {
+        let line_offsets = content
+            .iter()
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len() + 1;
+                Some(start)
+            })
+            .collect();
         Self {
+            lines: (0..content.len()).collect(),
+            line_offsets,
             content,
             index: 0,
             tokens: Vec::new(),
+            definitions: Rc::new(LinkDefinitions::new()),
+            metadata: Metadata::default(),
         }
     }
 
-    fn is_same_type(&self, other: BlockType) -> bool {
-        if let Some(token) = self.tokens.last() {
-            token.is_same_type(other)
-        } else {
-            false
+    // spanのバイト位置は元のテキストでの位置にする。\r\nの改行は2バイトとして数える
+    pub fn from_source(source: &str) -> Self {
+        let mut lexer = Self::new(source.lines().map(|s| s.to_string()).collect());
+        lexer.line_offsets = source
+            .split_inclusive('\n')
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len();
+                Some(start)
+            })
+            .collect();
+        lexer
+    }
+
+    // 先頭(<!-- url: -->などのコメント行の後ろも可)の---で囲まれたフロントマターを読み、本文から取り除く
+    fn process_front_matter(&mut self) {
+        let start = self
+            .content
+            .iter()
+            .position(|line| !(line.trim().starts_with("<!--") && line.trim().ends_with("-->")))
+            .unwrap_or(self.content.len());
+        if self.content.get(start).map(|line| line.trim_end()) != Some("---") {
+            return;
         }
+        let Some(end) =
+            (start + 1..self.content.len()).find(|&i| self.content[i].trim_end() == "---")
+        else {
+            return;
+        };
+
+        self.metadata = metadata::parse_front_matter(&self.content[start + 1..end]);
+        self.content.drain(start..=end);
+        self.lines.drain(start..=end);
     }
 
-    fn next(&mut self) {
-        self.index += 1;
+    // 本文を処理する前にリンクの定義を集め、定義の行は本文から取り除く
+    fn collect_link_definitions(&mut self) {
+        let mut definitions = LinkDefinitions::new();
+        let mut in_codeblock = false;
+        let (mut content, mut lines) = (vec![], vec![]);
+        for (line, number) in self.content.drain(..).zip(self.lines.drain(..)) {
+            if line.trim_start().starts_with("```") {
+                in_codeblock = !in_codeblock;
+            }
+            if !in_codeblock {
+                if let Some((label, definition)) = parse_link_definition(&line) {
+                    // 同じラベルが複数ある場合は最初のものを使う
+                    definitions.entry(label).or_insert(definition);
+                    continue;
+                }
+            }
+            content.push(line);
+            lines.push(number);
+        }
+        self.content = content;
+        self.lines = lines;
+        self.definitions = Rc::new(definitions);
     }
 
-    fn process_plain(&mut self) {
-        if self.is_same_type(BlockType::Plain) {
-            // 直前と同じトークンの場合は同じタイプに入れておく
-            let n = self.tokens.len();
-            self.tokens[n - 1].proceed_block_content(self.content[self.index].clone());
-        } else {
-            let mut token = BlockToken::new(BlockType::Plain);
-            token.proceed_block_content(self.content[self.index].clone());
-            self.tokens.push(token);
+    // content[index]のbyteバイト目の位置
+    fn position(&self, index: usize, byte: usize) -> Position {
+        let line = self.lines[index];
+        Position {
+            offset: self.line_offsets[line] + byte,
+            line: line + 1,
+            column: self.content[index][..byte].chars().count() + 1,
         }
+    }
 
-        self.next();
+    // content[first..=last]の行全体
+    fn span(&self, first: usize, last: usize) -> Span {
+        Span::new(
+            self.position(first, 0),
+            self.position(last, self.content[last].len()),
+        )
     }
 
-    fn process_h1(&mut self) {
-        let mut token = BlockToken::new(BlockType::h1);
-        token.proceed_block_content(self.content[self.index][2..].to_string());
-        self.tokens.push(token);
-        self.next();
+    // content[index]のrangeの部分をインラインの要素として処理する
+    fn inline(&self, index: usize, range: Range<usize>) -> Vec<InlineToken> {
+        let start = self.position(index, range.start);
+        let text = self.content[index][range].chars().collect();
+        InlineLexer::with_definitions(text, self.definitions.clone())
+            .at(start)
+            .tokenize()
+    }
+
+    // 複数行からなるブロックの中身。行の間には改行を入れる
+    fn push_line(&self, content: &mut Vec<InlineToken>, index: usize, range: Range<usize>) {
+        if let Some(last) = content.last() {
+            let line_break = Span::new(last.span.end, self.position(index, range.start));
+            content.push(InlineToken::new(Inline::LineBreak, line_break));
+        }
+        content.extend(self.inline(index, range));
     }
 
-    fn process_h2(&mut self) {
-        let mut token = BlockToken::new(BlockType::h2);
-        token.proceed_block_content(self.content[self.index][3..].to_string());
+    fn push(&mut self, kind: Block, first: usize, last: usize) {
+        let token = BlockToken::new(kind, self.span(first, last));
         self.tokens.push(token);
+    }
+
+    fn next(&mut self) {
+        self.index += 1;
+    }
+
+    fn process_plain(&mut self) {
+        let index = self.index;
+        let range = 0..self.content[index].len();
+        if let Some(BlockToken {
+            kind: Block::Plain { content },
+            span,
+        }) = self.tokens.last()
+        {
+            // 直前と同じトークンの場合は同じタイプに入れておく
+            let mut content = content.clone();
+            let start = span.start;
+            self.push_line(&mut content, index, range);
+            let span = Span::new(start, self.span(index, index).end);
+            *self.tokens.last_mut().unwrap() = BlockToken::new(Block::Plain { content }, span);
+        } else {
+            let mut content = vec![];
+            self.push_line(&mut content, index, range);
+            self.push(Block::Plain { content }, index, index);
+        }
+
         self.next();
     }
 
-    fn process_h3(&mut self) {
-        let mut token = BlockToken::new(BlockType::h3);
-        token.proceed_block_content(self.content[self.index][4..].to_string());
-        self.tokens.push(token);
+    // levelは # の数
+    fn process_heading(&mut self, level: usize) {
+        let index = self.index;
+        let content = self.inline(index, level + 1..self.content[index].len());
+        self.push(Block::Heading { level, content }, index, index);
         self.next();
     }
 
     fn process_empty(&mut self) {
-        let token = BlockToken::new(BlockType::Empty);
-        self.tokens.push(token);
+        self.push(Block::Empty, self.index, self.index + 1);
         // 2つ分の空行を消費したので2回next
         self.next();
         self.next();
     }
 
     fn process_hr(&mut self) {
-        let token = BlockToken::new(BlockType::Hr);
-        self.tokens.push(token);
+        self.push(Block::Hr, self.index, self.index);
         self.next();
     }
 
-    fn process_codeblock<R: RangeBounds<usize>>(&mut self, range: R, language: String) {
-        let start = match range.start_bound() {
-            Bound::Unbounded => 0,
-            Bound::Excluded(&s) => s + 1,
-            Bound::Included(&s) => s,
-        };
-        let end = match range.end_bound() {
-            Bound::Unbounded => self.content.len(),
-            Bound::Excluded(&t) => t.min(self.content.len()),
-            Bound::Included(&t) => (t + 1).min(self.content.len()),
-        };
+    // content[start]が開きの```、content[end]が閉じの```
+    fn process_codeblock(&mut self, start: usize, end: usize) {
+        let info = self.content[start][3..].trim().to_string();
+        let lang = info.split_whitespace().next().map(|s| s.to_string());
+        let code = self.content[start + 1..end].iter().join("\n");
 
-        let code = self.content[start + 1..end - 1].iter().join("\n");
-        let mut token = BlockToken::new(BlockType::CodeBlock);
-        token.process_block_content_as_plain_text(code);
-        token.process_block_content_as_plain_text(language);
-
-        self.tokens.push(token);
-        self.index = end;
+        self.push(Block::CodeBlock { lang, info, code }, start, end);
+        self.index = end + 1;
     }
 
     fn process_quote(&mut self) {
+        let first = self.index;
         let mut prev = false; // 直前が>で始まっていたか？
-        let mut quote_content = vec![];
-        for i in self.index..self.content.len() {
-            if self.content[i].is_empty() {
+        let mut quote_lines = vec![];
+        let mut last = first;
+        self.index = self.content.len();
+        for i in first..self.content.len() {
+            let line = &self.content[i];
+            if line.is_empty() {
                 // 問答無用で終了
                 self.index = i + 1;
                 break;
-            } else if self.content[i].starts_with(">") {
-                quote_content.push(self.content[i][1..].trim().to_string());
+            } else if let Some(rest) = line.strip_prefix(">") {
+                let start = line.len() - rest.trim_start().len();
+                quote_lines.push((i, start..start + rest.trim().len()));
                 prev = true;
             } else if prev {
-                quote_content.push(self.content[i].to_string());
+                quote_lines.push((i, 0..line.len()));
                 prev = false;
             } else {
                 self.index = i;
                 break;
             }
+            last = i;
         }
-        let mut token = BlockToken::new(BlockType::Quote);
-        for s in quote_content {
-            token.proceed_block_content(s);
+        let mut content = vec![];
+        for (i, range) in quote_lines {
+            self.push_line(&mut content, i, range);
         }
-        self.tokens.push(token);
+        self.push(Block::Quote { content }, first, last);
     }
 
-    fn process_footnote(&mut self) {
-        let v = self.content[self.index].split(':').collect_vec();
-        let id = v[0][2..v[0].len() - 1].to_string();
-        let text = v[1..].iter().join(":");
+    // [^id]: text の行を脚注の定義にする。idは最初の]:までで、:を含んでもよい
+    // idが空の場合などはfalseを返し、通常の行として処理させる
+    fn process_footnote(&mut self) -> bool {
+        let index = self.index;
+        let line = &self.content[index];
+        let Some(end) = line.strip_prefix("[^").and_then(|rest| rest.find("]:")) else {
+            return false;
+        };
+        let id = line[2..2 + end].to_string();
+        if id.trim().is_empty() {
+            return false;
+        }
 
-        let mut token = BlockToken::new(BlockType::FootNote);
-        // 1つ目がid, 2つ目がcontentということにしておく
-        token.process_block_content_as_plain_text(id);
-        token.proceed_block_content(text);
-        self.tokens.push(token);
+        let content = self.inline(index, 2 + end + 2..line.len());
+        self.push(Block::FootNote { id, content }, index, index);
         self.next();
+        true
     }
 
-    fn process_latex(&mut self, end: usize) {
-        let latex = self.content[self.index + 1..end].iter().join("");
-        let mut token = BlockToken::new(BlockType::Latex);
-        token.process_block_content_as_plain_text(latex);
-        dbg!(&token);
-        self.tokens.push(token);
+    // 行頭が$$や\[で始まる場合に、閉じデリミタのある行番号と数式の中身を探す
+    // 開始行で閉じる場合($$x$$)や、開始行・終了行に数式が書かれている場合にも対応する
+    fn find_display_math(&self, open: &str, close: &str) -> Option<(usize, String)> {
+        let rest = self.content[self.index].trim().strip_prefix(open)?;
+        if let Some(tex) = rest.strip_suffix(close) {
+            return Some((self.index, tex.to_string()));
+        }
+        if rest.contains(close) {
+            // $$x$$ hogeのように後ろに文章が続く場合はインラインの数式として扱う
+            return None;
+        }
+
+        let mut lines = vec![];
+        if !rest.trim().is_empty() {
+            lines.push(rest.to_string());
+        }
+        for i in self.index + 1..self.content.len() {
+            let line = self.content[i].trim_end();
+            if let Some(tex) = line.strip_suffix(close) {
+                if !tex.trim().is_empty() {
+                    lines.push(tex.to_string());
+                }
+                // alignなどで行構造が意味を持つので改行は残す
+                return Some((i, lines.join("\n")));
+            }
+            lines.push(line.to_string());
+        }
+        None
+    }
+
+    fn process_latex(&mut self, end: usize, latex: String) {
+        // \label{...}は取り除いておく。番号は後で振る
+        let (label, tex) = match extract_label(&latex) {
+            Some((label, tex)) => (Some(label), tex),
+            None => (None, latex),
+        };
+        let kind = Block::Latex {
+            tex,
+            label,
+            number: None,
+        };
+        self.push(kind, self.index, end);
         self.index = end;
         self.next();
     }
@@ -154,15 +301,15 @@ impl BlockLexer {
         'outer: while self.index < self.content.len() {
             if self.content[self.index].starts_with("# ") {
                 // h1
-                self.process_h1();
+                self.process_heading(1);
                 continue;
             } else if self.content[self.index].starts_with("## ") {
                 // h2
-                self.process_h2();
+                self.process_heading(2);
                 continue;
             } else if self.content[self.index].starts_with("### ") {
                 // h3
-                self.process_h3();
+                self.process_heading(3);
                 continue;
             } else if self.content[self.index].is_empty() {
                 // 空行(段落分け or 無視)
@@ -179,10 +326,9 @@ impl BlockLexer {
                 self.process_hr();
                 continue;
             } else if self.content[self.index].starts_with("```") {
-                let language = self.content[self.index].split_off(3);
                 for i in self.index + 1..self.content.len() {
                     if self.content[i].trim_start().starts_with("```") {
-                        self.process_codeblock(self.index..=i, language);
+                        self.process_codeblock(self.index, i);
                         continue 'outer;
                     }
                 }
@@ -191,20 +337,26 @@ impl BlockLexer {
                 self.process_quote();
                 continue;
             } else if self.content[self.index].starts_with("[^") {
-                let v = self.content[self.index].split("]:").collect_vec();
-                if v.len() >= 2 && v[0].len() >= 3 {
-                    // 始まりが[^(一文字以上) で、途中に]:があるのでfootnoteと判断
-                    self.process_footnote();
+                if self.process_footnote() {
                     continue;
                 }
             } else if self.content[self.index].starts_with("$$") {
-                for i in self.index + 1..self.content.len() {
-                    if self.content[i].trim().ends_with("$$") {
-                        self.process_latex(i);
-                        break;
-                    }
+                if let Some((end, latex)) = self.find_display_math("$$", "$$") {
+                    self.process_latex(end, latex);
+                    continue;
                 }
+            } else if self.content[self.index].starts_with("\\[") {
+                if let Some((end, latex)) = self.find_display_math("\\[", "\\]") {
+                    self.process_latex(end, latex);
+                    continue;
+                }
+            } else if parse_task_item(&self.content[self.index]).is_some() {
+                self.process_task_list();
                 continue;
+            } else if self.content[self.index].trim_start().starts_with("![[") {
+                if self.process_embed() {
+                    continue;
+                }
             } else if self.content[self.index].trim().starts_with("<!--")
                 && self.content[self.index].trim().ends_with("-->")
             {
@@ -217,8 +369,289 @@ impl BlockLexer {
         }
     }
 
+    // 連続する - [ ] の行を1つのタスクリストにまとめる
+    fn process_task_list(&mut self) {
+        let first = self.index;
+        let mut items = vec![];
+        while let Some((status, range)) = self
+            .content
+            .get(self.index)
+            .and_then(|line| parse_task_item(line))
+        {
+            items.push(TaskItem {
+                status,
+                content: self.inline(self.index, range),
+                span: self.span(self.index, self.index),
+            });
+            self.next();
+        }
+        self.push(Block::TaskList { items }, first, self.index - 1);
+    }
+
+    // 1行が ![[Note]] だけの場合はノートの埋め込みとして展開する
+    // 展開できなかった場合はfalseを返し、通常の行として処理させる
+    fn process_embed(&mut self) -> bool {
+        let line = self.content[self.index].trim();
+        let Some(target) = line.strip_prefix("![[").and_then(|s| s.strip_suffix("]]")) else {
+            return false;
+        };
+        // vaultを参照しない場合は展開せず、文中と同じくリンクとして扱う
+        if target.contains("]]") || asset::is_image(target) || resolver::is_detached() {
+            return false;
+        }
+
+        match embed::embed(target) {
+            Ok(children) => {
+                let kind = Block::Embed {
+                    target: target.to_string(),
+                    children,
+                };
+                self.push(kind, self.index, self.index);
+                self.next();
+                true
+            }
+            Err(e) => {
+                let span = self.span(self.index, self.index);
+                diagnostics::warn_at(span, format!("cannot embed `{target}`: {e}"));
+                false
+            }
+        }
+    }
+
+    // ラベル付きの数式に通し番号を振り、\eqrefや[[#label]]による参照を解決する
+    fn number_equations(&mut self) {
+        let mut numbers = HashMap::new();
+        for token in self.tokens.iter_mut() {
+            if let Block::Latex {
+                label: Some(label),
+                number,
+                ..
+            } = &mut token.kind
+            {
+                if numbers.contains_key(label) {
+                    diagnostics::warn_at(token.span, format!("duplicate equation label `{label}`"));
+                    continue;
+                }
+                let n = numbers.len() + 1;
+                *number = Some(n);
+                numbers.insert(label.clone(), n);
+            }
+        }
+        for token in self.tokens.iter_mut() {
+            token.resolve_equation_refs(&numbers);
+        }
+    }
+
     pub fn tokenize(&mut self) -> Vec<BlockToken> {
+        self.process_front_matter();
+        self.collect_link_definitions();
         self.consume();
+        self.number_equations();
+
+        let mut tags = vec![];
+        for token in self.tokens.iter() {
+            token.collect_tags(&mut tags);
+        }
+        for tag in tags {
+            self.metadata.add_tag(&tag);
+        }
+
+        let mut statuses = vec![];
+        for token in self.tokens.iter() {
+            token.collect_task_statuses(&mut statuses);
+        }
+        for status in statuses {
+            self.metadata.add_task(status);
+        }
         self.tokens.clone()
     }
+
+    // tokenizeの後に呼ぶ
+    pub fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+}
+
+// [label]: url "title" の形の行をパースする。脚注の定義([^id]: ...)は含まない
+fn parse_link_definition(line: &str) -> Option<(String, LinkDefinition)> {
+    let indent = line.len() - line.trim_start().len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = line.trim_start().strip_prefix('[')?;
+    if rest.starts_with('^') {
+        return None;
+    }
+    let (label, rest) = rest.split_once("]:")?;
+    if label.trim().is_empty() {
+        return None;
+    }
+
+    let rest = rest.trim();
+    let (url, title) = match rest.strip_prefix('<') {
+        Some(stripped) => {
+            let (url, title) = stripped.split_once('>')?;
+            (url, title.trim())
+        }
+        None => match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim()),
+            None => (rest, ""),
+        },
+    };
+    if url.is_empty() {
+        return None;
+    }
+    let title = if title.is_empty() {
+        None
+    } else {
+        let quoted = [('"', '"'), ('\'', '\''), ('(', ')')]
+            .iter()
+            .any(|&(open, close)| {
+                title.len() >= 2 && title.starts_with(open) && title.ends_with(close)
+            });
+        if !quoted {
+            return None;
+        }
+        Some(title[1..title.len() - 1].to_string())
+    };
+
+    Some((
+        normalize_label(label),
+        LinkDefinition {
+            url: url.to_string(),
+            title,
+        },
+    ))
+}
+
+// - [ ] text の形の行をパースし、(状態, 内容の範囲)を返す。*や+のリストやインデントされた行も含む
+fn parse_task_item(line: &str) -> Option<(char, Range<usize>)> {
+    let rest = line.trim_start();
+    let rest = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|marker| rest.strip_prefix(marker))?;
+    let rest = rest.strip_prefix('[')?;
+    let mut chars = rest.chars();
+    let status = chars.next()?;
+    let rest = chars.as_str().strip_prefix(']')?;
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let start = line.len() - rest.trim_start().len();
+    Some((status, start..start + rest.trim().len()))
+}
+
+// 数式中の\label{...}を取り出し、(ラベル, \labelを除いた数式)を返す
+fn extract_label(latex: &str) -> Option<(String, String)> {
+    let start = latex.find("\\label{")?;
+    let end = start + latex[start..].find('}')?;
+    let label = latex[start + "\\label{".len()..end].trim().to_string();
+    let rest = format!("{}{}", &latex[..start], &latex[end + 1..]);
+    Some((label, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render::html, testing};
+
+    fn to_html(source: &str) -> String {
+        testing::init();
+        let lines = source.lines().map(|line| line.to_string()).collect();
+        html::to_html(&BlockLexer::new(lines).tokenize())
+    }
+
+    #[test]
+    fn numbers_labelled_equations() {
+        let html = to_html("$$x\\label{a}$$\n$$y$$\n$$z \\label{b}$$\nsee \\eqref{b} and [[#a]]\n");
+        assert!(html.contains("id=\"a\">\\[x\\]<span class=\"equation-number\">(1)</span>"));
+        assert!(html.contains("id=\"b\">\\[z \\]<span class=\"equation-number\">(2)</span>"));
+        assert!(html.contains("\\[y\\]"));
+        let eqref_b = html.find("<a class=\"eqref\" href=\"#b\">(2)</a>").unwrap();
+        let eqref_a = html.find("<a class=\"eqref\" href=\"#a\">(1)</a>").unwrap();
+        assert!(eqref_b < eqref_a);
+
+        let html = to_html("$$x\\label{a\"b}$$\n\\eqref{a\"b}\n");
+        assert!(html.contains("id=\"a&quot;b\""));
+        assert!(html.contains("href=\"#a&quot;b\""));
+    }
+
+    #[test]
+    fn resolves_reference_links() {
+        let html = to_html("[text][Ref] and [ref]\n\n[REF]: https://example.com \"Title\"\n");
+        assert!(html.contains("<a href=\"https://example.com\" title=\"Title\">text</a>"));
+        assert!(html.contains("<a href=\"https://example.com\" title=\"Title\">ref</a>"));
+        // 定義の行は出力しない
+        assert!(!html.contains("[REF]"));
+    }
+
+    #[test]
+    fn collects_task_lists() {
+        let html = to_html("- [ ] open\n- [x] done **b**\n");
+        assert!(html.starts_with("<ul class=\"task-list\">"));
+        assert!(html.contains("disabled> open</li>"));
+        assert!(html.contains("disabled checked> done <strong>b</strong></li>"));
+
+        let lines = vec![
+            "- [ ] a".to_string(),
+            "- [X] b".to_string(),
+            "- [ ] c".to_string(),
+        ];
+        let mut lexer = BlockLexer::new(lines);
+        lexer.tokenize();
+        assert_eq!(
+            (lexer.metadata().tasks_open, lexer.metadata().tasks_closed),
+            (2, 1)
+        );
+    }
+
+    #[test]
+    fn records_source_spans() {
+        testing::init();
+        let source = "first\n## Title **bold**\n";
+        let lines = source.lines().map(|line| line.to_string()).collect();
+        let tokens = BlockLexer::new(lines).tokenize();
+        let heading = tokens.last().unwrap();
+        let Block::Heading { level, content } = &heading.kind else {
+            panic!("{tokens:?}");
+        };
+        assert_eq!(*level, 2);
+        assert_eq!((heading.span.start.line, heading.span.start.offset), (2, 6));
+        let bold = content.last().unwrap();
+        assert!(matches!(bold.kind, Inline::Bold(_)));
+        assert_eq!(
+            &source[bold.span.start.offset..bold.span.end.offset],
+            "**bold**"
+        );
+        assert_eq!(bold.span.start.column, 10);
+    }
+
+    fn tokenize(source: &str) -> Vec<BlockToken> {
+        testing::init();
+        BlockLexer::from_source(source).tokenize()
+    }
+
+    #[test]
+    fn splits_footnote_ids_at_the_delimiter() {
+        let tokens = tokenize("[^a:b]: note\n[^:]: colon\n[^]: empty\n");
+        let ids = tokens
+            .iter()
+            .filter_map(|token| match &token.kind {
+                Block::FootNote { id, .. } => Some(id.as_str()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(ids, ["a:b", ":"]);
+        assert!(matches!(tokens.last().unwrap().kind, Block::Plain { .. }));
+    }
+
+    #[test]
+    fn counts_crlf_in_offsets() {
+        let source = "first\r\n[^1]: note\r\n";
+        let tokens = tokenize(source);
+        let span = tokens.last().unwrap().span;
+        assert_eq!(span.start.offset, 7);
+        assert_eq!(span.start.line, 2);
+        assert_eq!(&source[span.start.offset..span.end.offset], "[^1]: note");
+    }
 }
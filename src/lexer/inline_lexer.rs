@@ -1,42 +1,118 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-};
+use std::{collections::HashMap, iter, rc::Rc};
 
 use itertools::Itertools;
 
 use crate::{
-    token::{InlineToken, InlineType},
-    util::get_path,
+    asset, link,
+    metadata::{is_tag_char, is_valid_tag},
+    resolver,
+    token::{Inline, InlineToken, Position, Span},
 };
 
+#[derive(Clone, Debug)]
+pub struct LinkDefinition {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+// 正規化したラベル -> 定義
+pub type LinkDefinitions = HashMap<String, LinkDefinition>;
+
+// ラベルは大文字小文字と連続する空白を区別しない
+pub fn normalize_label(label: &str) -> String {
+    label.split_whitespace().join(" ").to_lowercase()
+}
+
 #[derive(Debug)]
 pub struct InlineLexer {
     text: Vec<char>,
     temprary: Vec<char>,      // consumeしたtextをおいておく
+    temprary_start: usize,    // tempraryの先頭の文字の位置
     tokens: Vec<InlineToken>, // Token列
     index: usize,
+    definitions: Rc<LinkDefinitions>,
+    in_link: bool,       // リンクのテキストの中では自動リンクを行わない
+    start: Position,     // text[0]のソース中の位置
+    offsets: Vec<usize>, // text[0]からtext[i]までのバイト数。末尾には全体のバイト数が入る
 }
 
 impl InlineLexer {
     pub fn new(text: Vec<char>) -> Self {
+        Self::with_definitions(text, Rc::new(LinkDefinitions::new()))
+    }
+
+    pub fn with_definitions(text: Vec<char>, definitions: Rc<LinkDefinitions>) -> Self {
+        let offsets = iter::once(0)
+            .chain(text.iter().scan(0, |sum, c| {
+                *sum += c.len_utf8();
+                Some(*sum)
+            }))
+            .collect();
         InlineLexer {
             text,
             temprary: Vec::new(),
+            temprary_start: 0,
             tokens: Vec::new(),
             index: 0,
+            definitions,
+            in_link: false,
+            start: Position {
+                offset: 0,
+                line: 1,
+                column: 1,
+            },
+            offsets,
         }
     }
 
+    // textがソース中のstartの位置から始まることを指定する。spanはこれを基準にする
+    pub fn at(mut self, start: Position) -> Self {
+        self.start = start;
+        self
+    }
+
+    fn position(&self, i: usize) -> Position {
+        let i = i.min(self.text.len());
+        Position {
+            offset: self.start.offset + self.offsets[i],
+            line: self.start.line,
+            column: self.start.column + i,
+        }
+    }
+
+    // startから現在のindexの手前までの範囲
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(self.position(start), self.position(self.index))
+    }
+
+    // indexをトークンの後ろまで進めてから呼ぶ
+    fn push(&mut self, kind: Inline, start: usize) {
+        let token = InlineToken::new(kind, self.span_from(start));
+        self.tokens.push(token);
+    }
+
+    // text[l..r]を処理するLexer
+    fn sub_lexer(&self, l: usize, r: usize) -> InlineLexer {
+        InlineLexer::with_definitions(self.text[l..r].to_vec(), self.definitions.clone())
+            .at(self.position(l))
+    }
+
     fn next(&mut self) {
         self.index += 1;
     }
 
     fn consume_str(&mut self) {
-        self.temprary.push(self.text[self.index]);
+        self.push_temporary(self.text[self.index], self.index);
         self.next();
     }
 
+    fn push_temporary(&mut self, c: char, start: usize) {
+        if self.temprary.is_empty() {
+            self.temprary_start = start;
+        }
+        self.temprary.push(c);
+    }
+
     fn process_tempary_str(&mut self) {
         if self.temprary.is_empty() {
             // 何もする必要がない
@@ -44,118 +120,499 @@ impl InlineLexer {
         }
 
         let text = self.temprary.iter().join("");
-
-        let token = InlineToken::new(InlineType::Text, Some(text), None);
-        self.tokens.push(token);
+        self.push(Inline::Text(text), self.temprary_start);
         self.temprary.clear();
     }
 
     // Bold(e.g. **hoge**)等のdecoratorが複数ある場合にindexがずれないようにend_of_decoratorを指定する
-    fn process_decorator(
-        &mut self,
-        inline_type: InlineType,
-        l: usize,
-        r: usize,
-        end_of_decorator: usize,
-    ) {
-        let inline_text = self.text[l..r].iter().copied().collect_vec();
-        let children = InlineLexer::new(inline_text).tokenize();
-        let token = InlineToken::new(inline_type, None, Some(children));
-        self.tokens.push(token);
+    fn process_decorator(&mut self, l: usize, r: usize, end_of_decorator: usize) {
+        let start = self.index;
+        let children = self.sub_lexer(l, r).tokenize();
         self.index = end_of_decorator;
         self.next();
+        self.push(Inline::Bold(children), start);
     }
 
     fn process_inline_code(&mut self, end_of_decorator: usize) {
         // inline codeの中身はすべてplain textとして処理したいので別扱い
-        let inline_text = if self.index + 1 == end_of_decorator {
-            "".to_string()
-        } else {
-            self.text[self.index + 1..end_of_decorator]
-                .iter()
-                .copied()
-                .collect()
+        let start = self.index;
+        let code = self.text[self.index + 1..end_of_decorator].iter().collect();
+        self.index = end_of_decorator;
+        self.next();
+        self.push(Inline::Code(code), start);
+    }
+
+    fn push_url(
+        &mut self,
+        start: usize,
+        display: Vec<InlineToken>,
+        url: String,
+        title: Option<String>,
+        autolink: bool,
+    ) {
+        let kind = Inline::Url {
+            url,
+            title,
+            children: display,
+            autolink,
         };
+        self.push(kind, start);
+    }
 
-        let token = InlineToken::new(InlineType::Code, Some(inline_text), None);
-        self.tokens.push(token);
-        self.index = end_of_decorator;
+    // text[l..r]に対応するテキスト
+    fn plain_text(&self, text: String, l: usize, r: usize) -> Vec<InlineToken> {
+        let span = Span::new(self.position(l), self.position(r));
+        vec![InlineToken::new(Inline::Text(text), span)]
+    }
+
+    // リンクのテキストもインラインの要素として処理する。ただし中でさらにURLをリンクにはしない
+    fn lex_link_text(&self, l: usize, r: usize) -> Vec<InlineToken> {
+        let mut lexer = self.sub_lexer(l, r);
+        lexer.in_link = true;
+        lexer.tokenize()
+    }
+
+    // [text][ref] と [ref][] の形の参照リンク。定義が見つかった場合はtrueを返す
+    fn consume_reference_link(&mut self, start: usize, l: usize, r: usize) -> bool {
+        let label_start = self.index + 1;
+        let Some(end) = (label_start..self.text.len()).find(|&i| self.text[i] == ']') else {
+            return false;
+        };
+        let label = match self.text[label_start..end].iter().join("") {
+            label if label.trim().is_empty() => self.text[l..r].iter().join(""),
+            label => label,
+        };
+        let Some(definition) = self.definitions.get(&normalize_label(&label)).cloned() else {
+            return false;
+        };
+
+        let display = self.lex_link_text(l, r);
+        self.index = end;
         self.next();
+        self.push_url(start, display, definition.url, definition.title, false);
+        true
+    }
+
+    // '('の位置から (url "title") をパースし、(url, title, 閉じ括弧の位置) を返す
+    // urlは<...>で囲むか、対応の取れた括弧を含むことができる
+    fn parse_link_destination(&self, start: usize) -> Option<(String, Option<String>, usize)> {
+        let text = &self.text;
+        let skip_whitespace = |mut i: usize| {
+            while i < text.len() && text[i].is_whitespace() {
+                i += 1;
+            }
+            i
+        };
+
+        let mut i = skip_whitespace(start + 1);
+        let url = if text.get(i) == Some(&'<') {
+            let end = (i + 1..text.len()).find(|&j| text[j] == '>')?;
+            let url = text[i + 1..end].iter().join("");
+            i = end + 1;
+            url
+        } else {
+            let begin = i;
+            let mut depth = 0;
+            while i < text.len() {
+                match text[i] {
+                    '\\' => i += 1,
+                    '(' => depth += 1,
+                    ')' if depth == 0 => break,
+                    ')' => depth -= 1,
+                    c if c.is_whitespace() => break,
+                    _ => {}
+                }
+                i += 1;
+            }
+            text[begin..i.min(text.len())].iter().join("")
+        };
+
+        i = skip_whitespace(i);
+        let mut title = None;
+        if let Some(&open) = text.get(i) {
+            if matches!(open, '"' | '\'' | '(') {
+                let close = if open == '(' { ')' } else { open };
+                let end = (i + 1..text.len()).find(|&j| text[j] == close && text[j - 1] != '\\')?;
+                title = Some(text[i + 1..end].iter().join(""));
+                i = skip_whitespace(end + 1);
+            }
+        }
+        if text.get(i) != Some(&')') {
+            return None;
+        }
+        Some((url, title, i))
     }
 
     fn process_external_url(&mut self, end_of_decorator: usize) {
+        let start = self.index;
         if self.index + 1 == end_of_decorator {
             // "[]" という形で中身に何もない場合はtemporaryに突っ込んで終了しておく 空文字列のURLは意味がないので
-            self.temprary.push('[');
-            self.temprary.push(']');
+            self.push_temporary('[', start);
+            self.push_temporary(']', start);
             self.index = end_of_decorator;
             self.next();
         } else {
-            let display_text = self.text[self.index + 1..end_of_decorator]
-                .iter()
-                .copied()
-                .join("");
+            let (l, r) = (self.index + 1, end_of_decorator);
             // 後続にURLが続くことを期待して処理を続ける
-            // なお、続かない場合はURLを空にして処理をする
+            // なお、続かない場合は参照リンクの定義を探し、それもなければURLを空にして処理をする
             self.index = end_of_decorator;
             self.next();
-            let mut end_of_decorator = self.index;
-
-            let mut url = "".to_string();
-            if self.index + 1 < self.text.len() && self.text[self.index] == '(' {
-                for (i, &c) in self.text[self.index + 1..].iter().enumerate() {
-                    if c == ')' {
-                        end_of_decorator = self.index + 1 + i;
-                        if i != 0 {
-                            url = self.text[self.index + 1..self.index + 1 + i]
-                                .iter()
-                                .copied()
-                                .join("");
-                            break;
-                        }
+            if self.index < self.text.len() {
+                if self.text[self.index] == '[' && self.consume_reference_link(start, l, r) {
+                    return;
+                }
+                if self.text[self.index] == '(' {
+                    if let Some((url, title, end)) = self.parse_link_destination(self.index) {
+                        let display = self.lex_link_text(l, r);
+                        self.index = end;
+                        self.next();
+                        self.push_url(start, display, url, title, false);
+                        return;
                     }
                 }
             }
-            let token = InlineToken::new(
-                InlineType::Url,
-                Some(display_text),
-                Some(vec![InlineToken::new(InlineType::Text, Some(url), None)]),
-            );
-            self.tokens.push(token);
-            self.index = end_of_decorator;
-            self.next();
+
+            let label = normalize_label(&self.text[l..r].iter().join(""));
+            let (url, title) = match self.definitions.get(&label) {
+                // [ref] だけの省略形の参照リンク
+                Some(definition) => (definition.url.clone(), definition.title.clone()),
+                None => (String::new(), None),
+            };
+            let display = self.lex_link_text(l, r);
+            self.push_url(start, display, url, title, false);
         }
     }
 
-    fn process_footnote(&mut self, id: String) {
-        let token = InlineToken::new(InlineType::FootNote, Some(id), None);
-        self.tokens.push(token);
+    // <https://...> と <foo@example.com> の形の自動リンク
+    fn consume_autolink(&mut self) -> bool {
+        if self.in_link {
+            return false;
+        }
+        let Some(end) = (self.index + 1..self.text.len())
+            .take_while(|&i| !self.text[i].is_whitespace() && self.text[i] != '<')
+            .find(|&i| self.text[i] == '>')
+        else {
+            return false;
+        };
+        let inner = self.text[self.index + 1..end].iter().join("");
+        let url = if link::scheme(&inner).is_some() {
+            inner.clone()
+        } else if is_email(&inner) {
+            format!("mailto:{inner}")
+        } else {
+            // それ以外はHTMLのタグとしてそのまま出力する
+            return false;
+        };
+
+        self.process_tempary_str();
+        let start = self.index;
+        let display = self.plain_text(inner, start + 1, end);
+        self.index = end;
+        self.next();
+        self.push_url(start, display, url, None, true);
+        true
+    }
+
+    // GFMと同様に、文中の http://, https://, www. で始まるURLをリンクにする
+    fn consume_bare_url(&mut self) -> bool {
+        if self.in_link {
+            return false;
+        }
+        let at_boundary = self.index == 0 || {
+            let prev = self.text[self.index - 1];
+            prev.is_whitespace() || matches!(prev, '*' | '_' | '~' | '(')
+        };
+        if !at_boundary {
+            return false;
+        }
+        let rest = &self.text[self.index..];
+        let prefix = ["https://", "http://", "www."]
+            .into_iter()
+            .find(|prefix| rest.starts_with(&prefix.chars().collect_vec()));
+        let Some(prefix) = prefix else {
+            return false;
+        };
+
+        let mut end = rest
+            .iter()
+            .position(|&c| c.is_whitespace() || c == '<')
+            .unwrap_or(rest.len());
+        // 末尾の句読点と、対応の取れていない閉じ括弧はURLに含めない
+        loop {
+            match rest[..end].last() {
+                Some('?' | '!' | '.' | ',' | ':' | '*' | '_' | '~' | '\'' | '"') => end -= 1,
+                Some(')') => {
+                    let open = rest[..end].iter().filter(|&&c| c == '(').count();
+                    let close = rest[..end].iter().filter(|&&c| c == ')').count();
+                    if close > open {
+                        end -= 1;
+                    } else {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+        if end <= prefix.len() {
+            return false;
+        }
+
+        let display_text = rest[..end].iter().join("");
+        let url = if prefix == "www." {
+            format!("http://{display_text}")
+        } else {
+            display_text.clone()
+        };
+        self.process_tempary_str();
+        let start = self.index;
+        let display = self.plain_text(display_text, start, start + end);
+        self.index += end;
+        self.push_url(start, display, url, None, true);
+        true
+    }
+
+    // Obsidianのタグ (#algorithm, #atcoder/abc)。数字だけのものはタグとみなさない
+    fn consume_tag(&mut self) -> bool {
+        if self.in_link || (self.index > 0 && !self.text[self.index - 1].is_whitespace()) {
+            return false;
+        }
+        let mut end = self.index + 1;
+        while end < self.text.len() && is_tag_char(self.text[end]) {
+            end += 1;
+        }
+        while end > self.index + 1 && self.text[end - 1] == '/' {
+            end -= 1;
+        }
+        let tag = self.text[self.index + 1..end].iter().join("");
+        if tag.is_empty() || tag.chars().all(|c| c.is_ascii_digit()) || !is_valid_tag(&tag) {
+            return false;
+        }
+
+        self.process_tempary_str();
+        let start = self.index;
+        self.index = end;
+        self.push(Inline::Tag(tag), start);
+        true
+    }
+
+    fn process_latex(&mut self, display: bool, start: usize, end: usize, close_len: usize) {
+        self.process_tempary_str();
+        let token_start = self.index;
+        let tex = self.text[start..end].iter().join("");
+        self.index = end + close_len;
+        self.push(Inline::Latex { tex, display }, token_start);
+    }
+
+    // Pandocと同様のルールで$による数式を探す
+    // 開きの$の直後と閉じの$の直前は空白でなく、閉じの$の直後は数字でないものだけを数式とみなす
+    // 数式として処理できた場合はtrueを返す
+    fn consume_dollar(&mut self) -> bool {
+        let display = self.index + 1 < self.text.len() && self.text[self.index + 1] == '$';
+        let start = if display {
+            self.index + 2
+        } else {
+            self.index + 1
+        };
+        if start >= self.text.len() || (!display && self.text[start].is_whitespace()) {
+            return false;
+        }
+
+        let mut i = start;
+        while i < self.text.len() {
+            match self.text[i] {
+                '\\' => {
+                    // \$などのエスケープは閉じの$として扱わない
+                    i += 2;
+                    continue;
+                }
+                '$' if display => {
+                    // $$の中に単独の$がある場合は閉じとみなさない
+                    let closing = i > start && i + 1 < self.text.len() && self.text[i + 1] == '$';
+                    if closing {
+                        self.process_latex(true, start, i, 2);
+                        return true;
+                    }
+                }
+                '$' => {
+                    let followed_by_digit =
+                        i + 1 < self.text.len() && self.text[i + 1].is_ascii_digit();
+                    if i > start && !self.text[i - 1].is_whitespace() && !followed_by_digit {
+                        self.process_latex(false, start, i, 1);
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        false
+    }
+
+    // \(...\) と \[...\] による数式
+    fn consume_escaped_math(&mut self) -> bool {
+        if self.index + 1 >= self.text.len() {
+            return false;
+        }
+        let (display, close) = match self.text[self.index + 1] {
+            '(' => (false, ')'),
+            '[' => (true, ']'),
+            _ => return false,
+        };
+
+        let start = self.index + 2;
+        let mut i = start;
+        while i + 1 < self.text.len() {
+            if self.text[i] == '\\' {
+                if self.text[i + 1] == close {
+                    self.process_latex(display, start, i, 2);
+                    return true;
+                }
+                i += 2;
+            } else {
+                i += 1;
+            }
+        }
+        false
+    }
+
+    // wikilinkは[[#label]]の形で書かれていたか。番号が解決できなかった場合の表示に使う
+    fn process_equation_ref(&mut self, end_of_decorator: usize, label: String, wikilink: bool) {
+        self.process_tempary_str();
+        let start = self.index;
+        self.index = end_of_decorator;
         self.next();
+        let kind = Inline::EqRef {
+            label,
+            number: None,
+            wikilink,
+        };
+        self.push(kind, start);
     }
 
-    fn process_latex(&mut self) {
+    fn consume_eqref(&mut self) -> bool {
+        let prefix = "\\eqref{".chars().collect_vec();
+        if !self.text[self.index..].starts_with(&prefix) {
+            return false;
+        }
+        let start = self.index + prefix.len();
+        for i in start..self.text.len() {
+            if self.text[i] == '}' {
+                let label = self.text[start..i].iter().join("");
+                self.process_equation_ref(i, label, false);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn process_picture(&mut self, end_of_decorator: usize, picture: Inline) {
         self.process_tempary_str();
-        // 後ろの$を探す
-        for i in self.index + 1..self.text.len() {
-            if self.text[i] == '$' {
-                if self.index + 1 != i {
-                    let tex = self.text[self.index + 1..i].iter().join("");
-                    let token = InlineToken::new(InlineType::Latex, Some(tex), None);
-                    self.tokens.push(token);
-                    self.index = i;
+        let start = self.index;
+        self.index = end_of_decorator;
+        self.next();
+        self.push(picture, start);
+    }
+
+    fn process_obsidian_picture(&mut self, end_of_decorator: usize, path: String) {
+        // |の後ろは 300 / 300x200 ならサイズ、それ以外はaltとして扱う
+        let mut segments = path.split('|');
+        let name = segments.next().unwrap_or_default().trim().to_string();
+        let mut alt = name.clone();
+        let (mut width, mut height) = (None, None);
+        for segment in segments.map(|s| s.trim()) {
+            match parse_image_size(segment) {
+                Some((w, h)) => {
+                    width = Some(w);
+                    height = h;
                 }
-                self.next();
-                return;
+                None => alt = segment.to_string(),
             }
         }
+        let picture = Inline::Picture {
+            src: asset::picture_url(&name),
+            target: name,
+            alt,
+            title: None,
+            width,
+            height,
+            wikilink: true,
+        };
+        self.process_picture(end_of_decorator, picture);
+    }
+
+    fn consume_markdown_picture(&mut self) -> bool {
+        // [alt] の部分
+        let Some(close) = (self.index + 2..self.text.len()).find(|&i| self.text[i] == ']') else {
+            return false;
+        };
+        if close + 1 >= self.text.len() || self.text[close + 1] != '(' {
+            return false;
+        }
+        let Some((target, title, end)) = self.parse_link_destination(close + 1) else {
+            return false;
+        };
+        if target.is_empty() {
+            return false;
+        }
+
+        let alt = self.text[self.index + 2..close].iter().join("");
+        let src = if link::is_outside_vault(&target) {
+            target.clone()
+        } else {
+            asset::picture_url(&target)
+        };
+        let picture = Inline::Picture {
+            target,
+            src,
+            alt,
+            title: title.filter(|title| !title.is_empty()),
+            width: None,
+            height: None,
+            wikilink: false,
+        };
+        self.process_picture(end, picture);
+        true
     }
 
-    fn process_picture(&mut self, end_of_decorator: usize, path: String) {
+    // [[Note]], [[Note#Section]], [[Note|別名]] を解決してリンクにする
+    // 解決できなかった場合は別名かノート名だけを表示する。embedは![[Note]]の形で書かれていたか
+    fn process_internal_link(&mut self, end_of_decorator: usize, link: String, embed: bool) {
         self.process_tempary_str();
-        let token = InlineToken::new(InlineType::Picture, Some(path), None);
-        self.tokens.push(token);
+        let start = self.index;
+        let (target, alias) = match link.split_once('|') {
+            Some((target, alias)) => (target.trim(), Some(alias.trim().to_string())),
+            None => (link.as_str(), None),
+        };
+        let (note, section) = match target.split_once('#') {
+            Some((note, section)) => (note.trim(), Some(section.trim().to_string())),
+            None => (target.trim(), None),
+        };
+
+        let (url, display) = match resolver::get().resolve_note(note) {
+            Some(resolved) => {
+                let display = alias
+                    .clone()
+                    .or(resolved.title)
+                    .unwrap_or(target.to_string());
+                let url = match section.clone().or(resolved.anchor) {
+                    Some(anchor) => format!("{}#{}", resolved.url, anchor.replace(' ', "-")),
+                    None => resolved.url,
+                };
+                (Some(url), display)
+            }
+            None => (None, alias.clone().unwrap_or(target.to_string())),
+        };
+        let kind = Inline::WikiLink {
+            note: note.to_string(),
+            section,
+            alias,
+            url,
+            display,
+            embed,
+        };
         self.index = end_of_decorator;
         self.next();
+        self.push(kind, start);
     }
 
     fn consume_bracket(&mut self) {
@@ -165,15 +622,17 @@ impl InlineLexer {
             match self.text[self.index + 1] {
                 '^' => {
                     // この場合は脚注
-                    let mut text = vec![];
-                    for i in self.index + 2..self.text.len() {
-                        if self.text[i] == ']' {
-                            assert!(!text.is_empty()); // 対応が面倒くさいのでパースエラーということにしておく
-                            self.index = i;
-                            self.process_footnote(text.iter().join(""));
+                    let start = self.index;
+                    if let Some(end) =
+                        (self.index + 2..self.text.len()).find(|&i| self.text[i] == ']')
+                    {
+                        if end > self.index + 2 {
+                            let id = self.text[self.index + 2..end].iter().join("");
+                            self.index = end;
+                            self.next();
+                            self.push(Inline::FootNote { id }, start);
                             return;
                         }
-                        text.push(self.text[i]);
                     }
                 }
                 '[' => {
@@ -182,46 +641,14 @@ impl InlineLexer {
                     let mut prev = false; // 直前が]だったか？
                     for i in self.index + 2..self.text.len() {
                         if self.text[i] == ']' {
+                            if prev && link.first() == Some(&'#') {
+                                // [[#label]] は同じ文書内の数式への参照として扱う
+                                let label = link[1..].iter().join("");
+                                self.process_equation_ref(i, label, true);
+                                return;
+                            }
                             if prev {
-                                let mut flag = false; // 対応するurlが存在したか？
-                                if let Some(path) = get_path(link.iter().join("")) {
-                                    let file = File::open(path);
-                                    if let Ok(file) = file {
-                                        let reader = BufReader::new(file);
-                                        let first_line = reader.lines().next();
-                                        if let Some(Ok(line)) = first_line {
-                                            if line.starts_with("<!-- url: ")
-                                                && line.trim().ends_with("-->")
-                                            {
-                                                let trimed = line
-                                                    .trim_start_matches("<!-- url:")
-                                                    .trim_end_matches("-->")
-                                                    .trim()
-                                                    .to_string();
-                                                if !trimed.is_empty() {
-                                                    let token = InlineToken::new(
-                                                        InlineType::Url,
-                                                        Some(link.iter().join("")),
-                                                        Some(vec![InlineToken::new(
-                                                            InlineType::Text,
-                                                            Some(format!("{trimed}")),
-                                                            None,
-                                                        )]),
-                                                    );
-                                                    flag = true;
-                                                    self.tokens.push(token);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                if !flag {
-                                    // 処理されなかった場合はlink部分をplainなtextにする
-                                    // とはいいつつ、tempraryに突っ込んでおけば後でよしなにしてくれる
-                                    self.temprary.extend(link);
-                                }
-                                self.index = i;
-                                self.next();
+                                self.process_internal_link(i, link.iter().join(""), false);
                                 return;
                             }
                             prev = true;
@@ -234,12 +661,29 @@ impl InlineLexer {
             }
         }
 
-        for i in self.index + 1..self.text.len() {
-            if self.text[i] == ']' {
-                self.process_external_url(i);
-                break;
+        // 対応する]を探す。入れ子の[]とインラインコード、エスケープされた文字は飛ばす
+        let mut depth = 0;
+        let mut i = self.index + 1;
+        while i < self.text.len() {
+            match self.text[i] {
+                '\\' => i += 1,
+                '`' => {
+                    if let Some(end) = (i + 1..self.text.len()).find(|&j| self.text[j] == '`') {
+                        i = end;
+                    }
+                }
+                '[' => depth += 1,
+                ']' if depth == 0 => {
+                    self.process_external_url(i);
+                    return;
+                }
+                ']' => depth -= 1,
+                _ => {}
             }
+            i += 1;
         }
+        // 閉じていない[はただの文字
+        self.consume_str();
     }
 
     fn consume_inline_text(&mut self) {
@@ -254,7 +698,7 @@ impl InlineLexer {
                                 self.process_tempary_str();
 
                                 // [self.index+2, i)の区間を取り出して、その区間をLexerに掛ける
-                                self.process_decorator(InlineType::Bold, self.index + 2, i, i + 1);
+                                self.process_decorator(self.index + 2, i, i + 1);
                                 continue 'outer;
                             }
                         }
@@ -270,40 +714,80 @@ impl InlineLexer {
                             continue 'outer;
                         }
                     }
+                    self.consume_str();
                 }
                 '\\' => {
+                    if self.consume_escaped_math() || self.consume_eqref() {
+                        continue 'outer;
+                    }
                     // backslash: 次の文字を強制的にconsumeする。文末にある場合は無視。
                     if self.index + 1 < self.text.len() {
+                        self.push_temporary(self.text[self.index + 1], self.index);
+                        self.index += 2;
+                    } else {
                         self.next();
-                        self.consume_str();
                     }
                 }
+                '[' if self.in_link => {
+                    // リンクの中にリンクは作らない
+                    self.consume_str();
+                }
                 '[' => {
                     self.consume_bracket();
                 }
+                '#' => {
+                    if self.consume_tag() {
+                        continue 'outer;
+                    }
+                    self.consume_str();
+                }
+                '<' => {
+                    if self.consume_autolink() {
+                        continue 'outer;
+                    }
+                    self.consume_str();
+                }
+                'h' | 'w' => {
+                    if self.consume_bare_url() {
+                        continue 'outer;
+                    }
+                    self.consume_str();
+                }
                 '$' => {
                     // 数式
-                    self.process_latex();
+                    if self.consume_dollar() {
+                        continue 'outer;
+                    }
+                    self.consume_str();
                 }
-                '!' => {
-                    // 画像
-                    if self.index + 2 < self.text.len()
-                        && self.text[self.index + 1] == '['
-                        && self.text[self.index + 2] == '['
-                    {
-                        let mut path = vec![];
-                        for i in self.index + 3..self.text.len() - 1 {
-                            if self.text[i] == ']' && self.text[i + 1] == ']' {
-                                self.process_picture(i + 1, path.iter().join(""));
-                                continue 'outer;
+                '!' if self.index + 2 < self.text.len()
+                    && self.text[self.index + 1] == '['
+                    && self.text[self.index + 2] == '[' =>
+                {
+                    // Obsidianの画像 ![[file|300x200]]
+                    let mut path = vec![];
+                    for i in self.index + 3..self.text.len() - 1 {
+                        if self.text[i] == ']' && self.text[i + 1] == ']' {
+                            let path = path.iter().join("");
+                            if asset::is_image(&path) {
+                                self.process_obsidian_picture(i + 1, path);
                             } else {
-                                path.push(self.text[i]);
+                                // 文中のノートの埋め込みは展開できないので内部リンクにする
+                                self.process_internal_link(i + 1, path, true);
                             }
+                            continue 'outer;
+                        } else {
+                            path.push(self.text[i]);
                         }
-                        self.consume_str();
-                    } else {
-                        self.consume_str();
                     }
+                    self.consume_str();
+                }
+                '!' if self.index + 1 < self.text.len() && self.text[self.index + 1] == '[' => {
+                    // 通常のマークダウンの画像 ![alt](url "title")
+                    if self.consume_markdown_picture() {
+                        continue 'outer;
+                    }
+                    self.consume_str();
                 }
                 _ => {
                     self.consume_str();
@@ -319,3 +803,109 @@ impl InlineLexer {
         self.tokens.clone()
     }
 }
+
+// ![[file|300]] や ![[file|300x200]] のサイズ指定
+fn parse_image_size(text: &str) -> Option<(u32, Option<u32>)> {
+    let parse = |s: &str| {
+        s.chars()
+            .all(|c| c.is_ascii_digit())
+            .then(|| s.parse().ok())
+            .flatten()
+    };
+    match text.split_once('x') {
+        Some((w, h)) => Some((parse(w)?, Some(parse(h)?))),
+        None => Some((parse(text)?, None)),
+    }
+}
+
+// <user@example.com> の形のメールアドレス。ローカル部とドット区切りのドメインからなる
+pub fn is_email(text: &str) -> bool {
+    let Some((local, domain)) = text.split_once('@') else {
+        return false;
+    };
+    let is_local_char = |c: char| c.is_ascii_alphanumeric() || ".!#$%&'*+/=?^_`{|}~-".contains(c);
+    !local.is_empty()
+        && local.chars().all(is_local_char)
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        render::{html::HtmlRenderer, Renderer},
+        testing,
+    };
+
+    fn to_html(text: &str) -> String {
+        testing::init();
+        HtmlRenderer.render_inlines(&InlineLexer::new(text.chars().collect()).tokenize())
+    }
+
+    #[test]
+    fn parses_math_delimiters() {
+        assert_eq!(to_html("$a$ and \\(b\\)"), "\\(a\\) and \\(b\\)");
+        assert_eq!(to_html("$$c$$"), "\\[c\\]");
+        // エスケープされた$や金額の$は数式にしない
+        assert!(!to_html("\\$5 and \\$6").contains("\\("));
+        assert_eq!(to_html("$5 and $6"), "$5 and $6");
+    }
+
+    #[test]
+    fn parses_images() {
+        assert_eq!(
+            to_html("![a <b>](/img.png \"T\")"),
+            "<figure><img src=\"/img.png\" alt=\"a &lt;b&gt;\" title=\"T\" loading=\"lazy\" />\
+             <figcaption>T</figcaption></figure>"
+        );
+        assert_eq!(
+            to_html("![[x.png|300x200]]"),
+            "<img src=\"/assets/pictures/x.png\" alt=\"x.png\" width=\"300\" height=\"200\" loading=\"lazy\" />"
+        );
+        assert!(to_html("![[y.png|説明]]").contains("alt=\"説明\""));
+    }
+
+    #[test]
+    fn links_urls() {
+        assert_eq!(
+            to_html("<https://example.com/a> and https://example.com/b."),
+            "<a href=\"https://example.com/a\">https://example.com/a</a> and \
+             <a href=\"https://example.com/b\">https://example.com/b</a>."
+        );
+        assert_eq!(
+            to_html("<mail@example.com>"),
+            "<a href=\"mailto:mail@example.com\">mail@example.com</a>"
+        );
+        assert!(!to_html("`https://example.com`").contains("<a "));
+    }
+
+    #[test]
+    fn parses_inline_content_in_link_text() {
+        assert_eq!(
+            to_html("[**bold** and `code`](https://example.com \"title\")"),
+            "<a href=\"https://example.com\" title=\"title\"><strong>bold</strong> and \
+             <code class=\"inline-code\">code</code></a>"
+        );
+        // 括弧の対応がとれていればURLに含める
+        assert_eq!(
+            to_html("[x](https://example.com/a_(b))"),
+            "<a href=\"https://example.com/a_(b)\">x</a>"
+        );
+    }
+
+    #[test]
+    fn parses_tags() {
+        let html = to_html("#algorithm #atcoder/abc/ #123 a#b #c");
+        assert!(html.contains("<a class=\"tag\" href=\"/tags/algorithm/\">#algorithm</a>"));
+        assert!(html.contains("href=\"/tags/atcoder/abc/\">#atcoder/abc</a>/"));
+        assert!(html.contains("href=\"/tags/c/\">#c</a>"));
+        // 数字だけのものや単語の途中の#はタグにしない
+        assert_eq!(html.matches("class=\"tag\"").count(), 3);
+    }
+}
@@ -0,0 +1,21 @@
+pub mod asset;
+pub mod build;
+pub mod config;
+pub mod diagnostics;
+pub mod embed;
+pub mod graph;
+pub mod lexer;
+pub mod link;
+pub mod mathml;
+pub mod metadata;
+pub mod render;
+pub mod resolver;
+pub mod search;
+pub mod serve;
+pub mod tags;
+pub mod template;
+#[cfg(test)]
+mod testing;
+pub mod token;
+pub mod util;
+pub mod watch;
@@ -0,0 +1,203 @@
+use crate::{config, util::escape_html};
+
+// リンクのURLの書き換え規則。相対リンクにのみ適用する
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkRewrite {
+    ReplaceSuffix { from: String, to: String }, // e.g. .md => .html
+    Prefix(String),                             // ベースURLをつける
+}
+
+impl LinkRewrite {
+    // "from=>to" の形の規則をパースする
+    pub fn parse_suffix_rule(rule: &str) -> Result<Self, String> {
+        let (from, to) = rule.split_once("=>").ok_or(format!(
+            "invalid rewrite rule `{rule}` (expected `from=>to`)"
+        ))?;
+        Ok(Self::ReplaceSuffix {
+            from: from.trim().to_string(),
+            to: to.trim().to_string(),
+        })
+    }
+
+    fn apply(&self, url: &str) -> String {
+        match self {
+            Self::ReplaceSuffix { from, to } => {
+                // #や?以降はそのまま残す
+                let split = url.find(['#', '?']).unwrap_or(url.len());
+                let (path, rest) = url.split_at(split);
+                match path.strip_suffix(from.as_str()) {
+                    Some(stem) if !stem.is_empty() => format!("{stem}{to}{rest}"),
+                    _ => url.to_string(),
+                }
+            }
+            Self::Prefix(base) => {
+                format!(
+                    "{}/{}",
+                    base.trim_end_matches('/'),
+                    url.trim_start_matches('/')
+                )
+            }
+        }
+    }
+}
+
+// リンクに使ってよいスキーム。javascript:のようにクリックでスクリプトが動くものはリンクにしない
+const ALLOWED_SCHEMES: [&str; 5] = ["http", "https", "mailto", "tel", "ftp"];
+
+// URLのスキーム(小文字)。CommonMarkの絶対URIと同じく2〜32文字の英数字と+.-で、英字から始まるもの
+// ブラウザと同様に、前後の空白と途中のタブ・改行は無視する
+// URLの種類の判定(自動リンク、画像をvaultから探すか、外部リンクか)はすべてこれを使う
+pub fn scheme(url: &str) -> Option<String> {
+    let url: String = url
+        .trim_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    let (scheme, _) = url.split_once(':')?;
+    let valid = (2..=32).contains(&scheme.len())
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'));
+    valid.then(|| scheme.to_ascii_lowercase())
+}
+
+// URLのパスの部分。スキームとホストがあれば取り除く
+pub fn url_path(url: &str) -> &str {
+    match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => url,
+    }
+}
+
+// スキームがないか、許可されたスキームのURL
+pub fn is_allowed_url(url: &str) -> bool {
+    scheme(url).is_none_or(|scheme| ALLOWED_SCHEMES.contains(&scheme.as_str()))
+}
+
+// スキームを持つURLか、//から始まるスキームを省略したURL
+pub fn has_scheme(url: &str) -> bool {
+    url.starts_with("//") || scheme(url).is_some()
+}
+
+// 外部のURLや絶対パス。画像はvaultの中を探さずにそのまま使う
+pub fn is_outside_vault(url: &str) -> bool {
+    has_scheme(url) || url.starts_with('/')
+}
+
+fn host(url: &str) -> Option<&str> {
+    let rest = url.split_once("//")?.1;
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+// 設定されたサイトと異なるホストへのhttp(s)リンク
+pub fn is_external(url: &str) -> bool {
+    let is_web = url.starts_with("//") || matches!(scheme(url).as_deref(), Some("http" | "https"));
+    if !is_web {
+        return false;
+    }
+    match config::get().site_url.as_deref() {
+        Some(site) => host(site) != host(url),
+        None => true,
+    }
+}
+
+pub fn rewrite(url: &str) -> String {
+    // 空のURL(自身へのリンク)やページ内リンク、スキームを持つURLは書き換えない
+    if url.is_empty() || url.starts_with('#') || has_scheme(url) {
+        return url.to_string();
+    }
+    config::get()
+        .link_rewrites
+        .iter()
+        .fold(url.to_string(), |url, rule| rule.apply(&url))
+}
+
+// Urlトークンの<a>タグを組み立てる。許可されていないスキームのURLはリンクにせずにテキストだけを出力する
+pub fn render_anchor(url: &str, content: &str, title: Option<&str>) -> String {
+    if !is_allowed_url(url) {
+        return content.to_string();
+    }
+    let config = config::get();
+    let external = is_external(url);
+    let mut attributes = format!("href=\"{}\"", escape_html(&rewrite(url)));
+    if let Some(title) = title {
+        attributes.push_str(&format!(" title=\"{}\"", escape_html(title)));
+    }
+    let class = if external {
+        &config.external_link_class
+    } else {
+        &config.internal_link_class
+    };
+    if let Some(class) = class {
+        attributes.push_str(&format!(" class=\"{}\"", escape_html(class)));
+    }
+    if external && config.external_link_new_tab {
+        attributes.push_str(" target=\"_blank\" rel=\"noopener\"");
+    }
+    format!("<a {attributes}>{content}</a>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn escapes_href() {
+        testing::init();
+        let html = render_anchor("x\"onmouseover=\"alert(1)", "a", None);
+        assert_eq!(html, "<a href=\"x&quot;onmouseover=&quot;alert(1)\">a</a>");
+    }
+
+    #[test]
+    fn drops_disallowed_schemes() {
+        testing::init();
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            " java\tscript:alert(1)",
+            "vbscript:x",
+        ] {
+            assert_eq!(render_anchor(url, "a", None), "a", "{url}");
+        }
+        assert!(render_anchor("mailto:a@example.com", "a", None).starts_with("<a href="));
+        assert!(render_anchor("notes/a.html", "a", None).starts_with("<a href="));
+    }
+
+    #[test]
+    fn classifies_urls_consistently() {
+        testing::init();
+        assert_eq!(scheme("HTTPS://example.com").as_deref(), Some("https"));
+        assert_eq!(scheme("mailto:a@example.com").as_deref(), Some("mailto"));
+        // 1文字のスキームはWindowsのドライブ名と区別できないので認めない
+        assert_eq!(scheme("c:/x.png"), None);
+        assert_eq!(scheme("notes/a:b.md"), None);
+        assert!(has_scheme("//cdn.example.com/a.png"));
+        assert!(is_outside_vault("/assets/a.png"));
+        assert!(is_outside_vault("data:image/png;base64,AAAA"));
+        assert!(!is_outside_vault("pictures/a.png"));
+        assert!(is_external("//cdn.example.com/a.png"));
+        assert!(!is_external("mailto:a@example.com"));
+    }
+
+    #[test]
+    fn parses_suffix_rules() {
+        let rule = LinkRewrite::parse_suffix_rule(".md=>.html").unwrap();
+        assert_eq!(rule.apply("notes/a.md#x"), "notes/a.html#x");
+        assert!(LinkRewrite::parse_suffix_rule(".md").is_err());
+    }
+
+    #[test]
+    fn prefixes_relative_links_with_the_base_url() {
+        let rule = LinkRewrite::Prefix("https://example.com/notes/".to_string());
+        assert_eq!(rule.apply("/a.html"), "https://example.com/notes/a.html");
+        assert!(has_scheme("mailto:a@example.com"));
+        assert!(!has_scheme("notes/a.md"));
+        assert_eq!(
+            host("https://example.com:8080/a?b"),
+            Some("example.com:8080")
+        );
+    }
+}
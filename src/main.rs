@@ -1,37 +1,469 @@
-use std::{env, fs::File, io::Read, process::exit};
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
 
-use itertools::Itertools;
-use lexer::block_lexer::BlockLexer;
+use armp::{
+    build,
+    config::{self, Config},
+    diagnostics,
+    graph::LinkGraph,
+    render::{
+        json, latex, markdown,
+        text::{self, TextRenderer},
+        Renderer,
+    },
+    serve, template,
+    util::{set_vault_root, vault_root},
+    watch,
+};
 
-mod lexer;
-mod token;
-mod util;
+// 終了コード
+const EXIT_UNFORMATTED: i32 = 1; // fmt --checkで整形されていないファイルがあった
+const EXIT_USAGE: i32 = 2; // 引数の誤り
+const EXIT_INPUT: i32 = 3; // 入力を読めない
+const EXIT_OUTPUT: i32 = 4; // 出力を書き込めない
+const EXIT_CONFIG: i32 = 5; // 設定ファイルやvaultの指定の誤り
+const EXIT_FAILED: i32 = 6; // build、watch、serveの実行中のエラー
+
+// 値をとるオプション
+const VALUED_OPTIONS: [&str; 6] = [
+    "--format",
+    "--summary",
+    "--port",
+    "--vault",
+    "--config",
+    "-o",
+];
+const FLAGS: [&str; 8] = [
+    "--keep-math",
+    "--keep-code",
+    "--dot",
+    "--check",
+    "--write",
+    "--version",
+    "--help",
+    "-h",
+];
+
+fn usage_text(program: &str) -> String {
+    [
+        format!("Usage {program} [options] [--format html|text|json|latex] <md file path | ->"),
+        format!("      {program} [options] --format text [--keep-math] [--keep-code] <md file path | ->"),
+        format!("      {program} [options] --summary <N> <md file path | ->"),
+        format!("      {program} [options] build <output dir>"),
+        format!("      {program} [options] watch <vault> <output dir>"),
+        format!("      {program} [options] serve [--port <N>]"),
+        format!("      {program} [options] graph [--dot]"),
+        format!("      {program} [options] fmt [--check | --write] <md file path | ->..."),
+        String::new(),
+        "Options:".to_string(),
+        "  --vault <dir>     vault directory (overrides KNOWLEDGES)".to_string(),
+        "  --config <file>   config file of `key = value` lines".to_string(),
+        "  -o <file>         write the output to a file instead of stdout".to_string(),
+        "  --version         print the version".to_string(),
+    ]
+    .join("\n")
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("{}", usage_text(program));
+    exit(EXIT_USAGE);
+}
+
+fn fail(code: i32, message: impl Display) -> ! {
+    eprintln!("Error: {message}");
+    exit(code);
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage {} <md file path>", args[0]);
-        exit(1);
+    // -o --version のように値が抜けている場合は、値の位置のオプションを実行せずに誤りとする
+    if let Some(name) = missing_value(&args) {
+        eprintln!("Error: {name} requires a value");
+        usage(&args[0]);
+    }
+    if args.iter().any(|arg| arg == "--version") {
+        println!("armp {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", usage_text(&args[0]));
+        return;
+    }
+    if let Some(option) = args
+        .iter()
+        .skip(1)
+        .enumerate()
+        .find(|(i, arg)| {
+            arg.starts_with('-')
+                && *arg != "-"
+                && !VALUED_OPTIONS.contains(&arg.as_str())
+                && !FLAGS.contains(&arg.as_str())
+                && !VALUED_OPTIONS.contains(&args[*i].as_str())
+        })
+        .map(|(_, arg)| arg)
+    {
+        eprintln!("Error: unknown option `{option}`");
+        usage(&args[0]);
+    }
+    let positionals = positionals(&args);
+    let Some(&command) = positionals.first() else {
+        usage(&args[0]);
+    };
+    if let Err(e) = check_options(&args, command) {
+        eprintln!("Error: {e}");
+        usage(&args[0]);
+    }
+
+    let port = option_value(&args, "--port").map_or(8000, |port| {
+        port.parse().unwrap_or_else(|_| {
+            eprintln!("Error: invalid port `{port}`");
+            usage(&args[0]);
+        })
+    });
+
+    // 設定とvaultはそれを使う処理より前に決める
+    let mut config = match option_value(&args, "--config") {
+        Some(path) => Config::from_file(Path::new(path)).unwrap_or_else(|e| fail(EXIT_CONFIG, e)),
+        None => Config::from_env(),
+    };
+    // プレビューするページへのリンクは内部リンクとして扱う
+    if command == "serve" {
+        config.site_url = Some(serve::origin(port));
+    }
+    let _ = config::init(config);
+    // watchはvaultを引数で受け取る
+    let vault = match command {
+        "watch" => positionals.get(1).copied(),
+        _ => option_value(&args, "--vault"),
+    };
+    if let Some(vault) = vault {
+        let path = PathBuf::from(vault);
+        if !path.is_dir() {
+            fail(EXIT_CONFIG, format!("vault `{vault}` is not a directory"));
+        }
+        let _ = set_vault_root(path);
+    }
+
+    match command {
+        "build" => {
+            let Some(output) = positionals.get(1) else {
+                usage(&args[0]);
+            };
+            require_vault();
+            if let Err(e) = build::build_vault(Path::new(output)) {
+                fail(EXIT_FAILED, e);
+            }
+        }
+        "watch" => {
+            let Some(output) = positionals.get(2) else {
+                usage(&args[0]);
+            };
+            if let Err(e) = watch::watch(Path::new(output)) {
+                fail(EXIT_FAILED, e);
+            }
+        }
+        "serve" => {
+            if let Err(e) = serve::serve(require_vault(), port) {
+                fail(EXIT_FAILED, e);
+            }
+        }
+        "graph" => {
+            require_vault();
+            let graph = LinkGraph::from_vault();
+            if args.iter().any(|arg| arg == "--dot") {
+                write_output(&args, &graph.to_dot());
+            } else {
+                write_output(&args, &graph.to_json());
+            }
+        }
+        "fmt" => fmt(&args, &positionals[1..]),
+        _ => render(&args, command),
+    }
+}
+
+// コマンドで使われないオプションは黙って無視せずに引数の誤りにする
+fn check_options(args: &[String], command: &str) -> Result<(), String> {
+    let allowed: &[&str] = match command {
+        "build" => &["--vault"],
+        // watchはvaultを引数で受け取る
+        "watch" => &[],
+        "serve" => &["--vault", "--port"],
+        "graph" => &["--vault", "--dot", "-o"],
+        "fmt" => &["--vault", "--check", "--write", "-o"],
+        _ => &[
+            "--vault",
+            "--format",
+            "--summary",
+            "--keep-math",
+            "--keep-code",
+            "-o",
+        ],
+    };
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if VALUED_OPTIONS.contains(&arg.as_str()) {
+            iter.next();
+        }
+        if arg.starts_with('-')
+            && arg != "-"
+            && arg != "--config"
+            && !allowed.contains(&arg.as_str())
+        {
+            return Err(match command {
+                "build" | "watch" | "serve" | "graph" | "fmt" => {
+                    format!("`{arg}` cannot be used with `{command}`")
+                }
+                _ => format!("`{arg}` cannot be used when converting a note"),
+            });
+        }
     }
 
-    let mut f = File::open(&args[1]).expect("file not found");
+    // --summaryと--keep-*はテキストの出力でだけ使う。--formatがない場合はrenderと同じ形式にする
+    let has = |name: &str| args.iter().any(|arg| arg == name);
+    let format =
+        option_value(args, "--format").unwrap_or(if has("--summary") { "text" } else { "html" });
+    for option in ["--summary", "--keep-math", "--keep-code"] {
+        if has(option) && format != "text" {
+            return Err(format!(
+                "`{option}` cannot be used with `--format {format}`"
+            ));
+        }
+    }
+    if has("--summary") && (has("--keep-math") || has("--keep-code")) {
+        return Err("`--keep-math` and `--keep-code` cannot be used with `--summary`".to_string());
+    }
+    Ok(())
+}
+
+// オプションとその値を除いた引数
+fn positionals(args: &[String]) -> Vec<&str> {
+    let mut positionals = vec![];
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if VALUED_OPTIONS.contains(&arg.as_str()) {
+            iter.next();
+        } else if arg == "-" || !arg.starts_with('-') {
+            positionals.push(arg.as_str());
+        }
+    }
+    positionals
+}
+
+// 値をとるオプションのうち、値がないか値の代わりに別のオプションが書かれているもの
+fn missing_value(args: &[String]) -> Option<&str> {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if VALUED_OPTIONS.contains(&arg.as_str()) {
+            match iter.next() {
+                Some(value) if value == "-" || !value.starts_with('-') => {}
+                _ => return Some(arg.as_str()),
+            }
+        }
+    }
+    None
+}
+
+// --name value の形のオプションの値
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let i = args.iter().position(|arg| arg == name)?;
+    match args.get(i + 1) {
+        Some(value) => Some(value.as_str()),
+        None => {
+            eprintln!("Error: {name} requires a value");
+            usage(&args[0]);
+        }
+    }
+}
+
+// vault全体を扱うコマンドではvaultの指定を必須にする
+fn require_vault() -> &'static Path {
+    match vault_root() {
+        Some(root) if root.is_dir() => root,
+        Some(root) => fail(
+            EXIT_CONFIG,
+            format!("vault `{}` is not a directory", root.display()),
+        ),
+        None => fail(
+            EXIT_CONFIG,
+            "no vault is given; pass --vault or set KNOWLEDGES",
+        ),
+    }
+}
+
+// -の場合は標準入力から読む
+fn read_input(path: &str) -> String {
     let mut content = String::new();
-    f.read_to_string(&mut content).expect("cannot read file");
+    let result = if path == "-" {
+        io::stdin().read_to_string(&mut content).map(|_| ())
+    } else {
+        fs::read_to_string(path).map(|text| content = text)
+    };
+    if let Err(e) = result {
+        fail(EXIT_INPUT, format!("cannot read {path}: {e}"));
+    }
+    content
+}
 
-    // 環境変数の登録をチェック
-    match env::var("KNOWLEDGES") {
-        Ok(_) => {}
-        Err(_) => {
-            eprintln!("Error: Environment variable KNOWLEDGES is not set.");
-            exit(1);
+// -oが指定されていればそのファイルに、なければ標準出力に書き出す
+fn write_output(args: &[String], text: &str) {
+    let result = match option_value(args, "-o") {
+        Some(path) => fs::write(path, text).map_err(|e| format!("cannot write {path}: {e}")),
+        None => io::stdout()
+            .write_all(text.as_bytes())
+            .map_err(|e| format!("cannot write to stdout: {e}")),
+    };
+    if let Err(e) = result {
+        fail(EXIT_OUTPUT, e);
+    }
+}
+
+fn fmt(args: &[String], paths: &[&str]) {
+    let check = args.iter().any(|arg| arg == "--check");
+    let write = args.iter().any(|arg| arg == "--write");
+    if paths.is_empty() {
+        usage(&args[0]);
+    }
+    let mut output = String::new();
+    let mut unformatted = false;
+    for &path in paths {
+        let content = read_input(path);
+        let formatted = markdown::format(&content);
+        // 標準入力は書き換えられないので、--writeでも整形した結果を出力する
+        if (check || write) && path != "-" {
+            if formatted == content {
+                continue;
+            }
+            unformatted = true;
+            if write {
+                if let Err(e) = fs::write(path, &formatted) {
+                    fail(EXIT_OUTPUT, format!("cannot write {path}: {e}"));
+                }
+            } else {
+                output.push_str(&format!("{path}\n"));
+            }
+        } else if check {
+            if formatted != content {
+                unformatted = true;
+                output.push_str("-\n");
+            }
+        } else {
+            output.push_str(&formatted);
         }
     }
+    write_output(args, &output);
+    // --checkでは整形されていないファイルがあれば失敗にする
+    if check && unformatted {
+        exit(EXIT_UNFORMATTED);
+    }
+}
 
-    let linebreaked_content: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-    let mut block_lexer = BlockLexer::new(linebreaked_content);
-    let tokens = block_lexer.tokenize();
+// 1つのノートを変換して出力する
+fn render(args: &[String], path: &str) {
+    let summary = option_value(args, "--summary").map(|n| {
+        n.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: invalid number `{n}` for --summary");
+            usage(&args[0]);
+        })
+    });
+    // --summaryはテキストの出力で使う
+    let format =
+        option_value(args, "--format").unwrap_or(if summary.is_some() { "text" } else { "html" });
+    if !["html", "text", "json", "latex"].contains(&format) {
+        eprintln!("Error: unknown format `{format}`");
+        usage(&args[0]);
+    }
 
-    let html = tokens.iter().map(|elm| elm.to_html()).join("\n");
+    let content = read_input(path);
+    let path = Path::new(path);
+    let output = match format {
+        "html" => {
+            // バックリンクはvault全体を読む必要があるのでテンプレートで使われている場合のみ求める
+            let graph = template::uses("backlinks").then(LinkGraph::from_vault);
+            build::render_note(path, &content, graph.as_ref()).html
+        }
+        "text" => {
+            let note = build::parse_note(path, &content);
+            match summary {
+                Some(length) => text::summary(&note.tokens, length),
+                None => {
+                    let mut renderer = TextRenderer {
+                        keep_math: args.iter().any(|arg| arg == "--keep-math"),
+                        keep_code: args.iter().any(|arg| arg == "--keep-code"),
+                    };
+                    renderer.render(&note.tokens)
+                }
+            }
+        }
+        "json" => {
+            let (note, diagnostics) = diagnostics::collect(|| build::parse_note(path, &content));
+            json::document(&note, &diagnostics)
+        }
+        _ => {
+            let note = build::parse_note(path, &content);
+            let name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let title = note.metadata.title.as_ref().unwrap_or(&name);
+            // 末尾に改行を含むので、他の形式と揃えるために取り除く
+            latex::document(title, &note).trim_end().to_string()
+        }
+    };
+    write_output(args, &format!("{output}\n"));
+}
 
-    println!("{}", html);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn separates_positionals_from_option_values() {
+        let args = args("armp --vault v -o out.html --keep-math - note.md");
+        assert_eq!(positionals(&args), ["-", "note.md"]);
+        assert_eq!(option_value(&args, "--vault"), Some("v"));
+        assert_eq!(option_value(&args, "-o"), Some("out.html"));
+        assert_eq!(option_value(&args, "--port"), None);
+        assert_eq!(missing_value(&args), None);
+    }
+
+    #[test]
+    fn rejects_options_in_place_of_values() {
+        assert_eq!(
+            missing_value(&args("armp -o --version note.md")),
+            Some("-o")
+        );
+        assert_eq!(
+            missing_value(&args("armp note.md --vault")),
+            Some("--vault")
+        );
+        assert_eq!(missing_value(&args("armp -o - --version")), None);
+    }
+
+    fn check(args: &str) -> Result<(), String> {
+        let args: Vec<String> = args.split_whitespace().map(|s| s.to_string()).collect();
+        let command = positionals(&args)[0];
+        check_options(&args, command)
+    }
+
+    #[test]
+    fn rejects_options_that_would_be_ignored() {
+        assert!(check("armp --summary 3 --format html note.md").is_err());
+        assert!(check("armp --keep-code note.md").is_err());
+        assert!(check("armp --vault v watch v out").is_err());
+        assert!(check("armp --port 1 build out").is_err());
+        assert!(check("armp --summary 3 note.md").is_ok());
+        assert!(check("armp --format text --keep-math -o out.txt note.md").is_ok());
+        assert!(check("armp --config c --vault v serve --port 1").is_ok());
+        assert!(check("armp -o - fmt --check note.md").is_ok());
+    }
 }
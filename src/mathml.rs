@@ -0,0 +1,711 @@
+use itertools::Itertools;
+
+use crate::{
+    config::{self, MathRenderer},
+    diagnostics,
+};
+
+// 数式を設定に応じて出力する。MathMLに変換できなかった場合はデリミタ出力に戻す
+pub fn render(tex: &str, display: bool) -> String {
+    let delimited = if display {
+        format!("\\[{tex}\\]")
+    } else {
+        format!("\\({tex}\\)")
+    };
+    if config::get().math != MathRenderer::MathML {
+        return delimited;
+    }
+
+    match to_mathml(tex, display) {
+        Ok(mathml) => mathml,
+        Err(commands) => {
+            diagnostics::warn(format!(
+                "unsupported TeX command(s) {} in `{}`, falling back to delimiter output",
+                commands.iter().join(", "),
+                tex.trim()
+            ));
+            delimited
+        }
+    }
+}
+
+// 対応していないコマンドがあった場合はそのコマンド名の一覧を返す
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, Vec<String>> {
+    let mut parser = Parser::new(tex, display);
+    let body = parser.parse_top();
+    if !parser.unsupported.is_empty() {
+        return Err(parser.unsupported.into_iter().unique().collect());
+    }
+
+    let display_attr = if display { "block" } else { "inline" };
+    Ok(format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"{display_attr}\"><mrow>{body}</mrow></math>"
+    ))
+}
+
+// parse_rowが止まった理由
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stop {
+    Eof,
+    Brace,   // }
+    Amp,     // &
+    Newline, // \\
+    End,     // \end{...}
+    Right,   // \right
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    display: bool,
+    unsupported: Vec<String>,
+}
+
+impl Parser {
+    fn new(tex: &str, display: bool) -> Self {
+        Self {
+            chars: tex.chars().collect(),
+            index: 0,
+            display,
+            unsupported: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn next(&mut self) {
+        self.index += 1;
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    // '\'の直後から呼ぶ。英字の並びか記号1文字をコマンド名として読む
+    fn read_command(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphabetic() {
+                name.push(c);
+                self.next();
+            } else {
+                break;
+            }
+        }
+        if name.is_empty() {
+            if let Some(c) = self.peek() {
+                name.push(c);
+                self.next();
+            }
+        }
+        name
+    }
+
+    // {...}の中身をそのまま読む。{で始まらない場合は1文字だけ読む
+    fn read_raw_group(&mut self) -> String {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            let c = self.peek().map(String::from).unwrap_or_default();
+            self.next();
+            return c;
+        }
+        self.next();
+        let mut depth = 0;
+        let mut raw = String::new();
+        while let Some(c) = self.peek() {
+            self.next();
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            raw.push(c);
+        }
+        raw
+    }
+
+    fn parse_top(&mut self) -> String {
+        let mut out = String::new();
+        loop {
+            let (row, stop) = self.parse_row();
+            out.push_str(&row);
+            // トップレベルに残った}や&、\\は無視する
+            if stop == Stop::Eof {
+                return out;
+            }
+        }
+    }
+
+    fn parse_row(&mut self) -> (String, Stop) {
+        let mut out = String::new();
+        loop {
+            self.skip_whitespace();
+            let stop = match self.peek() {
+                None => Some(Stop::Eof),
+                Some('}') => Some(Stop::Brace),
+                Some('&') => Some(Stop::Amp),
+                Some('\\') => {
+                    let start = self.index;
+                    self.next();
+                    match self.read_command().as_str() {
+                        "\\" => Some(Stop::Newline),
+                        "end" => {
+                            self.read_raw_group();
+                            return (out, Stop::End);
+                        }
+                        "right" => return (out, Stop::Right),
+                        _ => {
+                            self.index = start;
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            if let Some(stop) = stop {
+                if stop != Stop::Eof && stop != Stop::Newline {
+                    self.next();
+                }
+                return (out, stop);
+            }
+
+            let (base, big_operator) = match self.peek() {
+                Some('^') | Some('_') => ("<mrow></mrow>".to_string(), false),
+                _ => self.parse_atom(),
+            };
+            out.push_str(&self.parse_scripts(base, big_operator));
+        }
+    }
+
+    fn parse_scripts(&mut self, base: String, big_operator: bool) -> String {
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.parse_argument());
+                }
+                Some('^') if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.parse_argument());
+                }
+                Some('\'') if sup.is_none() => {
+                    self.next();
+                    sup = Some("<mo>′</mo>".to_string());
+                }
+                _ => break,
+            }
+        }
+
+        // 表示数式中の総和などは上下に添字を置く
+        let (both, under, over) = if big_operator && self.display {
+            ("munderover", "munder", "mover")
+        } else {
+            ("msubsup", "msub", "msup")
+        };
+        match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{under}>{base}{sub}</{under}>"),
+            (None, Some(sup)) => format!("<{over}>{base}{sup}</{over}>"),
+            (Some(sub), Some(sup)) => format!("<{both}>{base}{sub}{sup}</{both}>"),
+        }
+    }
+
+    // 添字や\fracの引数。{...}か1文字分
+    fn parse_argument(&mut self) -> String {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.next();
+                let (row, _) = self.parse_row();
+                format!("<mrow>{row}</mrow>")
+            }
+            Some(c) if c.is_ascii_digit() => {
+                self.next();
+                format!("<mn>{c}</mn>")
+            }
+            _ => self.parse_atom().0,
+        }
+    }
+
+    // 戻り値の2つ目は\sumのような上下に添字を取る演算子かどうか
+    fn parse_atom(&mut self) -> (String, bool) {
+        let Some(c) = self.peek() else {
+            return (String::new(), false);
+        };
+        self.next();
+        match c {
+            '{' => {
+                let (row, _) = self.parse_row();
+                (format!("<mrow>{row}</mrow>"), false)
+            }
+            '\\' => self.parse_command(),
+            c if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(d) = self.peek() {
+                    let is_decimal = d == '.'
+                        && self
+                            .chars
+                            .get(self.index + 1)
+                            .is_some_and(|n| n.is_ascii_digit());
+                    if d.is_ascii_digit() || is_decimal {
+                        number.push(d);
+                        self.next();
+                    } else {
+                        break;
+                    }
+                }
+                (format!("<mn>{number}</mn>"), false)
+            }
+            '-' => ("<mo>−</mo>".to_string(), false),
+            '+' | '=' | '(' | ')' | '[' | ']' | '|' | ',' | ';' | ':' | '!' | '/' | '*' | '.'
+            | '?' => (format!("<mo>{c}</mo>"), false),
+            '<' => ("<mo>&lt;</mo>".to_string(), false),
+            '>' => ("<mo>&gt;</mo>".to_string(), false),
+            '~' => ("<mspace width=\"0.333em\"/>".to_string(), false),
+            c => (format!("<mi>{}</mi>", escape(&c.to_string())), false),
+        }
+    }
+
+    fn parse_command(&mut self) -> (String, bool) {
+        let name = self.read_command();
+        let atom = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument();
+                let denominator = self.parse_argument();
+                format!("<mfrac>{numerator}{denominator}</mfrac>")
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let n = self.parse_argument();
+                let k = self.parse_argument();
+                format!("<mrow><mo>(</mo><mfrac linethickness=\"0\">{n}{k}</mfrac><mo>)</mo></mrow>")
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                if self.peek() == Some('[') {
+                    self.next();
+                    let mut raw = String::new();
+                    while let Some(c) = self.peek() {
+                        self.next();
+                        if c == ']' {
+                            break;
+                        }
+                        raw.push(c);
+                    }
+                    let mut inner = Parser::new(&raw, false);
+                    let index = inner.parse_top();
+                    self.unsupported.extend(inner.unsupported);
+                    let radicand = self.parse_argument();
+                    format!("<mroot>{radicand}<mrow>{index}</mrow></mroot>")
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument())
+                }
+            }
+            "text" | "textrm" | "textnormal" | "mbox" | "textit" | "textbf" => {
+                let text = self.read_raw_group();
+                format!("<mtext>{}</mtext>", escape(&text).replace(' ', "\u{a0}"))
+            }
+            "mathrm" | "operatorname" => {
+                let text = self.read_raw_group();
+                format!("<mi mathvariant=\"normal\">{}</mi>", escape(&text))
+            }
+            "mathbf" | "boldsymbol" => self.variant("bold"),
+            "mathit" => self.variant("italic"),
+            "mathbb" => self.variant("double-struck"),
+            "mathcal" => self.variant("script"),
+            "mathfrak" => self.variant("fraktur"),
+            "left" => {
+                let open = self.read_delimiter();
+                let (row, stop) = self.parse_row();
+                let close = if stop == Stop::Right {
+                    self.read_delimiter()
+                } else {
+                    String::new()
+                };
+                format!("<mrow><mo fence=\"true\">{open}</mo>{row}<mo fence=\"true\">{close}</mo></mrow>")
+            }
+            "begin" => {
+                let env = self.read_raw_group();
+                self.parse_environment(&env)
+            }
+            "label" | "tag" => {
+                self.read_raw_group();
+                String::new()
+            }
+            "nonumber" | "notag" | "displaystyle" | "textstyle" | "limits" | "nolimits" => {
+                String::new()
+            }
+            "," => "<mspace width=\"0.167em\"/>".to_string(),
+            ":" | ">" => "<mspace width=\"0.222em\"/>".to_string(),
+            ";" => "<mspace width=\"0.278em\"/>".to_string(),
+            " " => "<mspace width=\"0.25em\"/>".to_string(),
+            "!" => "<mspace width=\"-0.167em\"/>".to_string(),
+            "quad" => "<mspace width=\"1em\"/>".to_string(),
+            "qquad" => "<mspace width=\"2em\"/>".to_string(),
+            "{" | "}" | "|" | "%" | "#" | "$" | "_" => format!("<mo>{name}</mo>"),
+            "&" => "<mo>&amp;</mo>".to_string(),
+            "overline" | "bar" => self.accent("¯"),
+            "hat" | "widehat" => self.accent("^"),
+            "tilde" | "widetilde" => self.accent("~"),
+            "vec" => self.accent("→"),
+            "dot" => self.accent("˙"),
+            "ddot" => self.accent("¨"),
+            "underline" => format!(
+                "<munder accentunder=\"true\">{}<mo>_</mo></munder>",
+                self.parse_argument()
+            ),
+            "mod" | "bmod" => "<mo lspace=\"0.278em\" rspace=\"0.278em\">mod</mo>".to_string(),
+            "pmod" => format!(
+                "<mrow><mspace width=\"1em\"/><mo>(</mo><mo rspace=\"0.278em\">mod</mo>{}<mo>)</mo></mrow>",
+                self.parse_argument()
+            ),
+            name => {
+                if let Some(op) = big_operator(name) {
+                    return (format!("<mo largeop=\"true\">{op}</mo>"), op != "∫");
+                }
+                if is_limit_function(name) {
+                    return (format!("<mo movablelimits=\"true\">{name}</mo>"), true);
+                }
+                if is_function(name) {
+                    format!("<mi>{name}</mi>")
+                } else if let Some(letter) = greek(name) {
+                    format!("<mi>{letter}</mi>")
+                } else if let Some(symbol) = identifier_symbol(name) {
+                    format!("<mi>{symbol}</mi>")
+                } else if let Some(op) = operator_symbol(name) {
+                    format!("<mo>{op}</mo>")
+                } else {
+                    self.unsupported.push(format!("\\{name}"));
+                    format!("<merror><mtext>\\{}</mtext></merror>", escape(name))
+                }
+            }
+        };
+        (atom, false)
+    }
+
+    fn variant(&mut self, variant: &str) -> String {
+        let text = self.read_raw_group();
+        format!("<mi mathvariant=\"{variant}\">{}</mi>", escape(&text))
+    }
+
+    fn accent(&mut self, mark: &str) -> String {
+        let base = self.parse_argument();
+        format!("<mover accent=\"true\">{base}<mo>{mark}</mo></mover>")
+    }
+
+    // \left, \right の直後の区切り文字
+    fn read_delimiter(&mut self) -> String {
+        self.skip_whitespace();
+        let Some(c) = self.peek() else {
+            return String::new();
+        };
+        self.next();
+        if c != '\\' {
+            return match c {
+                '.' => String::new(),
+                '<' => "&lt;".to_string(),
+                '>' => "&gt;".to_string(),
+                c => c.to_string(),
+            };
+        }
+        let name = self.read_command();
+        match name.as_str() {
+            "{" | "}" => name,
+            "|" => "‖".to_string(),
+            "langle" => "⟨".to_string(),
+            "rangle" => "⟩".to_string(),
+            "lfloor" => "⌊".to_string(),
+            "rfloor" => "⌋".to_string(),
+            "lceil" => "⌈".to_string(),
+            "rceil" => "⌉".to_string(),
+            "vert" => "|".to_string(),
+            "Vert" => "‖".to_string(),
+            _ => {
+                self.unsupported.push(format!("\\{name}"));
+                String::new()
+            }
+        }
+    }
+
+    fn parse_environment(&mut self, env: &str) -> String {
+        let (open, close) = match env {
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" | "cases" => ("{", ""),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            _ => ("", ""),
+        };
+        let alignment = match env {
+            "align" | "align*" | "aligned" | "split" | "eqnarray" | "eqnarray*" | "alignat"
+            | "alignat*" => &["right", "left"][..],
+            "cases" => &["left"][..],
+            "gather" | "gather*" | "gathered" | "matrix" | "pmatrix" | "bmatrix" | "Bmatrix"
+            | "vmatrix" | "Vmatrix" | "smallmatrix" => &["center"][..],
+            "array" => {
+                // 列指定は読み捨てて中央揃えにする
+                self.read_raw_group();
+                &["center"][..]
+            }
+            _ => {
+                self.unsupported.push(format!("\\begin{{{env}}}"));
+                &["center"][..]
+            }
+        };
+        if env.starts_with("alignat") {
+            // 列数の指定は不要
+            self.read_raw_group();
+        }
+
+        let mut rows: Vec<Vec<String>> = vec![];
+        let mut cells = vec![];
+        loop {
+            let (cell, stop) = self.parse_row();
+            cells.push(cell);
+            match stop {
+                Stop::Amp => continue,
+                Stop::Newline => rows.push(std::mem::take(&mut cells)),
+                Stop::End | Stop::Eof => {
+                    // 最後の\\の後ろの空行は捨てる
+                    if !(cells.len() == 1 && cells[0].is_empty()) {
+                        rows.push(cells);
+                    }
+                    break;
+                }
+                Stop::Brace | Stop::Right => {}
+            }
+        }
+
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(1);
+        let column_align = (0..columns)
+            .map(|i| alignment[i % alignment.len()])
+            .join(" ");
+        let body = rows
+            .iter()
+            .map(|row| {
+                let cells = row
+                    .iter()
+                    .map(|cell| format!("<mtd><mrow>{cell}</mrow></mtd>"))
+                    .join("");
+                format!("<mtr>{cells}</mtr>")
+            })
+            .join("");
+        let table = format!(
+            "<mtable displaystyle=\"{}\" columnalign=\"{column_align}\">{body}</mtable>",
+            self.display
+        );
+
+        if open.is_empty() && close.is_empty() {
+            table
+        } else {
+            format!(
+                "<mrow><mo fence=\"true\">{open}</mo>{table}<mo fence=\"true\">{close}</mo></mrow>"
+            )
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn greek(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        _ => return None,
+    })
+}
+
+fn big_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        "bigvee" => "⋁",
+        "bigwedge" => "⋀",
+        _ => return None,
+    })
+}
+
+fn is_limit_function(name: &str) -> bool {
+    matches!(
+        name,
+        "lim" | "max" | "min" | "sup" | "inf" | "limsup" | "liminf" | "argmax" | "argmin"
+    )
+}
+
+fn is_function(name: &str) -> bool {
+    matches!(
+        name,
+        "sin"
+            | "cos"
+            | "tan"
+            | "cot"
+            | "sec"
+            | "csc"
+            | "arcsin"
+            | "arccos"
+            | "arctan"
+            | "sinh"
+            | "cosh"
+            | "tanh"
+            | "log"
+            | "ln"
+            | "lg"
+            | "exp"
+            | "gcd"
+            | "lcm"
+            | "det"
+            | "deg"
+            | "dim"
+            | "ker"
+            | "arg"
+            | "Pr"
+    )
+}
+
+fn identifier_symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "infty" => "∞",
+        "emptyset" | "varnothing" => "∅",
+        "ell" => "ℓ",
+        "hbar" => "ℏ",
+        "aleph" => "ℵ",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "Re" => "ℜ",
+        "Im" => "ℑ",
+        _ => return None,
+    })
+}
+
+fn operator_symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "le" | "leq" => "≤",
+        "ge" | "geq" => "≥",
+        "ne" | "neq" => "≠",
+        "ll" => "≪",
+        "gg" => "≫",
+        "equiv" => "≡",
+        "approx" => "≈",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "propto" => "∝",
+        "times" => "×",
+        "div" => "÷",
+        "cdot" => "⋅",
+        "pm" => "±",
+        "mp" => "∓",
+        "ast" => "∗",
+        "circ" => "∘",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "neg" | "lnot" => "¬",
+        "forall" => "∀",
+        "exists" => "∃",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" | "implies" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" | "iff" => "⇔",
+        "mapsto" => "↦",
+        "mid" => "∣",
+        "nmid" => "∤",
+        "parallel" => "∥",
+        "perp" => "⊥",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "prime" => "′",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_tex_to_mathml() {
+        let mathml = to_mathml("x^2 + \\frac{a}{b}", true).unwrap();
+        assert!(mathml.starts_with("<math"));
+        assert!(mathml.contains("display=\"block\""));
+        assert!(mathml.contains("<msup>") && mathml.contains("<mfrac>"));
+        let unsupported = to_mathml("\\unknowncommand{x}", false).unwrap_err();
+        assert!(unsupported.iter().any(|c| c.contains("unknowncommand")));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{config, diagnostics};
+
+// ノートのメタデータ。フロントマターと本文から集める
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub tags: Vec<String>, // フロントマターのタグと本文中の#タグ
+    pub tasks_open: usize,
+    pub tasks_closed: usize,
+}
+
+impl Metadata {
+    pub fn add_tag(&mut self, tag: &str) {
+        let tag = tag.trim().trim_start_matches('#');
+        if tag.is_empty() {
+            return;
+        }
+        if !is_valid_tag(tag) {
+            diagnostics::warn(format!("invalid tag `{tag}`"));
+            return;
+        }
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    pub fn add_task(&mut self, status: char) {
+        if is_task_closed(status) {
+            self.tasks_closed += 1;
+        } else {
+            self.tasks_open += 1;
+        }
+    }
+}
+
+// 本文中の#タグに使える文字
+pub fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '/')
+}
+
+// タグはページの出力先のパスになるので、空の階層や.、\などを含むものは使えない
+pub fn is_valid_tag(tag: &str) -> bool {
+    tag.chars().all(is_tag_char) && tag.split('/').all(|segment| !segment.is_empty())
+}
+
+// [x]は完了、[-]は中止として閉じたタスクに数える。[ ]や進行中の[/]などそれ以外は未完了
+pub fn is_task_closed(status: char) -> bool {
+    matches!(status, 'x' | 'X' | '-')
+}
+
+// --- で囲まれたフロントマターの中身をパースする
+// YAMLのうち、key: value、key: [a, b]、続く行の - a によるリストだけに対応する
+pub fn parse_front_matter(lines: &[String]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut current_key = String::new();
+    for line in lines {
+        if let Some(item) = line.trim_start().strip_prefix("- ") {
+            set_value(&mut metadata, &current_key, unquote(item));
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        current_key = key.trim().to_string();
+        let value = value.trim();
+        if let Some(list) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            for item in list.split(',') {
+                set_value(&mut metadata, &current_key, unquote(item));
+            }
+        } else if !value.is_empty() {
+            set_value(&mut metadata, &current_key, unquote(value));
+        }
+    }
+    metadata
+}
+
+fn set_value(metadata: &mut Metadata, key: &str, value: &str) {
+    match key {
+        "title" => metadata.title = Some(value.to_string()),
+        // tags: a b のように空白区切りで書かれることもある
+        "tags" | "tag" => value
+            .split_whitespace()
+            .for_each(|tag| metadata.add_tag(tag)),
+        _ => {}
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+// タグのページのURL。設定の{tag}をタグで置き換える
+pub fn tag_url(tag: &str) -> String {
+    config::get().tag_url.replace("{tag}", tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_front_matter() {
+        let metadata = parse_front_matter(&lines(
+            "title: \"Note\"\ntags: [a, 'b']\ntag:\n  - c\n  - a\n",
+        ));
+        assert_eq!(metadata.title.as_deref(), Some("Note"));
+        assert_eq!(metadata.tags, ["a", "b", "c"]);
+        assert_eq!(parse_front_matter(&lines("tags: x #y")).tags, ["x", "y"]);
+    }
+
+    #[test]
+    fn ignores_tags_that_are_not_paths() {
+        let lines = ["tags: [atcoder/abc, ../../x, a\\b, a//b, ., ok]".to_string()];
+        assert_eq!(parse_front_matter(&lines).tags, ["atcoder/abc", "ok"]);
+    }
+}
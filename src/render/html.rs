@@ -0,0 +1,231 @@
+use itertools::Itertools;
+
+use crate::{
+    link, mathml, metadata,
+    render::Renderer,
+    token::{Block, BlockToken, Inline, InlineToken},
+    util::escape_html,
+};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HtmlRenderer;
+
+impl Renderer for HtmlRenderer {
+    fn render_block(&mut self, block: &BlockToken) -> String {
+        render_block(self, block)
+    }
+
+    fn render_inline(&mut self, inline: &InlineToken) -> String {
+        render_inline(self, inline)
+    }
+}
+
+pub fn to_html(blocks: &[BlockToken]) -> String {
+    HtmlRenderer.render(blocks)
+}
+
+// 子要素はrendererで変換する
+pub fn render_inline<R: Renderer + ?Sized>(renderer: &mut R, inline: &InlineToken) -> String {
+    match &inline.kind {
+        Inline::Text(text) => text.clone(),
+        Inline::Bold(children) => {
+            let children_html = renderer.render_inlines(children);
+            format!("<strong>{}</strong>", children_html)
+        }
+        Inline::LineBreak => "<br>".to_string(),
+        Inline::Code(code) => format!("<code class=\"inline-code\">{code}</code>"),
+        Inline::Url {
+            url,
+            title,
+            children,
+            ..
+        } => {
+            let content = renderer.render_inlines(children);
+            link::render_anchor(url, &content, title.as_deref().filter(|t| !t.is_empty()))
+        }
+        Inline::WikiLink { url, display, .. } => match url {
+            Some(url) => link::render_anchor(url, display, None),
+            None => display.clone(),
+        },
+        Inline::FootNote { id } => format!("<span id=\"{id}\"></span>"),
+        Inline::Latex { tex, display } => mathml::render(tex, *display),
+        Inline::EqRef {
+            label,
+            number,
+            wikilink,
+        } => match number {
+            Some(number) => format!(
+                "<a class=\"eqref\" href=\"#{}\">({number})</a>",
+                escape_html(label)
+            ),
+            // 見つからない場合は書かれたままのテキストを表示する
+            None if *wikilink => format!("#{label}"),
+            None => "(??)".to_string(),
+        },
+        Inline::Tag(tag) => format!(
+            "<a class=\"tag\" href=\"{}\">#{tag}</a>",
+            escape_html(&metadata::tag_url(tag))
+        ),
+        Inline::Picture {
+            src,
+            alt,
+            title,
+            width,
+            height,
+            ..
+        } => {
+            let mut img = format!(
+                "<img src=\"{}\" alt=\"{}\"",
+                escape_html(src),
+                escape_html(alt)
+            );
+            if let Some(title) = title {
+                img.push_str(&format!(" title=\"{}\"", escape_html(title)));
+            }
+            if let Some(width) = width {
+                img.push_str(&format!(" width=\"{width}\""));
+            }
+            if let Some(height) = height {
+                img.push_str(&format!(" height=\"{height}\""));
+            }
+            img.push_str(" loading=\"lazy\" />");
+
+            match title {
+                Some(title) => format!(
+                    "<figure>{img}<figcaption>{}</figcaption></figure>",
+                    escape_html(title)
+                ),
+                None => img,
+            }
+        }
+    }
+}
+
+// 子要素はrendererで変換する
+pub fn render_block<R: Renderer + ?Sized>(renderer: &mut R, block: &BlockToken) -> String {
+    let mut join = |content: &[InlineToken]| {
+        content
+            .iter()
+            .map(|it| renderer.render_inline(it))
+            .join("\n")
+    };
+    match &block.kind {
+        // h1はページのタイトルに使うので1つずらす
+        Block::Heading { level, content } => {
+            format!("<h{0}>{1}</h{0}>", level + 1, join(content))
+        }
+        Block::Plain { content } if content.iter().any(is_figure) => {
+            split_at_figures(renderer, content)
+        }
+        Block::Plain { content } => format!("<p>{}</p>", join(content)),
+        Block::Empty => "<br>".to_string(),
+        Block::Hr => "<hr>".to_string(),
+        Block::CodeBlock { lang, code, .. } => {
+            let content = code.replace("<", "&lt;").replace(">", "&gt;");
+            let language = lang.as_deref().unwrap_or_default();
+
+            format!("<pre><code class=\"codeblock language-{language}\">{content}</code></pre>")
+        }
+        Block::Quote { content } => format!("<blockquote>{}</blockquote>", join(content)),
+        Block::FootNote { id, content } => {
+            let text = renderer.render_inlines(content);
+            format!("<foot-note for=\"{id}\">{text}</foot-note>")
+        }
+        Block::Embed { target, children } => {
+            let children = children
+                .iter()
+                .map(|tk| renderer.render_block(tk))
+                .join("\n");
+            format!(
+                "<div class=\"embed\" data-embed=\"{}\">{children}</div>",
+                escape_html(target)
+            )
+        }
+        Block::TaskList { items } => {
+            let items = items
+                .iter()
+                .map(|item| {
+                    let text = renderer.render_inlines(&item.content);
+                    let checked = if item.status == ' ' { "" } else { " checked" };
+                    format!(
+                        "<li class=\"task-list-item\" data-task=\"{}\"><input type=\"checkbox\" class=\"task-list-item-checkbox\" disabled{checked}> {text}</li>",
+                        escape_html(item.status.to_string().trim())
+                    )
+                })
+                .join("\n");
+            format!("<ul class=\"task-list\">\n{items}\n</ul>")
+        }
+        Block::Latex { tex, label, number } => {
+            let math = mathml::render(tex, true);
+            match (label, number) {
+                (Some(label), Some(number)) => format!(
+                    "<div class=\"equation\" id=\"{}\">{math}<span class=\"equation-number\">({number})</span></div>",
+                    escape_html(label)
+                ),
+                _ => math,
+            }
+        }
+    }
+}
+
+// タイトルのついた画像は<figure>になる
+fn is_figure(inline: &InlineToken) -> bool {
+    matches!(&inline.kind, Inline::Picture { title: Some(_), .. })
+}
+
+// <figure>は<p>の中に書けないので、図の前後で段落を分ける。図だけの段落は<p>で囲まない
+fn split_at_figures<R: Renderer + ?Sized>(renderer: &mut R, content: &[InlineToken]) -> String {
+    let is_blank = |inline: &InlineToken| match &inline.kind {
+        Inline::LineBreak => true,
+        Inline::Text(text) => text.trim().is_empty(),
+        _ => false,
+    };
+    let mut html = vec![];
+    for (figure, group) in &content.iter().chunk_by(|inline| is_figure(inline)) {
+        let group = group.collect_vec();
+        if figure {
+            html.extend(
+                group
+                    .into_iter()
+                    .map(|inline| renderer.render_inline(inline)),
+            );
+            continue;
+        }
+        let start = group.iter().position(|inline| !is_blank(inline));
+        let end = group.iter().rposition(|inline| !is_blank(inline));
+        if let (Some(start), Some(end)) = (start, end) {
+            let paragraph = group[start..=end]
+                .iter()
+                .map(|inline| renderer.render_inline(inline))
+                .join("\n");
+            html.push(format!("<p>{paragraph}</p>"));
+        }
+    }
+    html.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::block_lexer::BlockLexer, testing};
+
+    fn html(content: &str) -> String {
+        testing::init();
+        let lines = content.lines().map(|line| line.to_string()).collect();
+        to_html(&BlockLexer::new(lines).tokenize())
+    }
+
+    #[test]
+    fn does_not_put_figures_in_paragraphs() {
+        let figure = "<figure><img src=\"https://example.com/a.png\" alt=\"a\" title=\"A\" loading=\"lazy\" /><figcaption>A</figcaption></figure>";
+        assert_eq!(html("![a](https://example.com/a.png \"A\")"), figure);
+        assert_eq!(
+            html("text ![a](https://example.com/a.png \"A\")\n\nmore"),
+            format!("<p>text </p>\n{figure}\n<p>more</p>")
+        );
+        assert_eq!(
+            html("![a](https://example.com/a.png)"),
+            "<p><img src=\"https://example.com/a.png\" alt=\"a\" loading=\"lazy\" /></p>"
+        );
+    }
+}
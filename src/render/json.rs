@@ -0,0 +1,318 @@
+use itertools::Itertools;
+
+use crate::{
+    build::ParsedNote,
+    diagnostics::Diagnostic,
+    metadata::is_task_closed,
+    render::Renderer,
+    resolver,
+    token::{Block, BlockToken, Inline, InlineToken, Position, Span},
+    util::json_string,
+};
+
+// 出力するJSONの形式の版。フィールドの削除や意味の変更をした場合に上げる(フィールドの追加では上げない)
+pub const VERSION: u32 = 1;
+
+// トークンをJSONに変換する。各ノードは"type"で種類を表し、"span"に元のテキストでの位置を持つ
+// token.rsの型をそのまま写すのではなく、外部から読まれることを前提にした名前にしている
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonRenderer;
+
+impl Renderer for JsonRenderer {
+    fn render_inline(&mut self, inline: &InlineToken) -> String {
+        let fields = match &inline.kind {
+            Inline::Text(text) => vec![("type", json_string("text")), ("text", json_string(text))],
+            Inline::Bold(children) => vec![
+                ("type", json_string("bold")),
+                ("children", self.render_inlines(children)),
+            ],
+            Inline::Code(code) => vec![("type", json_string("code")), ("code", json_string(code))],
+            Inline::LineBreak => vec![("type", json_string("line_break"))],
+            Inline::Url {
+                url,
+                title,
+                children,
+                autolink,
+            } => vec![
+                ("type", json_string("link")),
+                ("url", json_string(url)),
+                ("title", optional(title.as_deref())),
+                ("autolink", autolink.to_string()),
+                ("children", self.render_inlines(children)),
+            ],
+            Inline::WikiLink {
+                note,
+                section,
+                alias,
+                url,
+                display,
+                embed,
+            } => vec![
+                ("type", json_string("wikilink")),
+                ("note", json_string(note)),
+                ("section", optional(section.as_deref())),
+                ("alias", optional(alias.as_deref())),
+                ("url", optional(url.as_deref())),
+                ("display", json_string(display)),
+                ("embed", embed.to_string()),
+            ],
+            Inline::FootNote { id } => vec![
+                ("type", json_string("footnote_ref")),
+                ("id", json_string(id)),
+            ],
+            Inline::Latex { tex, display } => vec![
+                ("type", json_string("math")),
+                ("tex", json_string(tex)),
+                ("display", display.to_string()),
+            ],
+            Inline::Picture {
+                target,
+                src,
+                alt,
+                title,
+                width,
+                height,
+                wikilink,
+            } => vec![
+                ("type", json_string("image")),
+                ("target", json_string(target)),
+                ("src", json_string(src)),
+                ("alt", json_string(alt)),
+                ("title", optional(title.as_deref())),
+                ("width", number(*width)),
+                ("height", number(*height)),
+                ("wikilink", wikilink.to_string()),
+            ],
+            Inline::EqRef {
+                label,
+                number: n,
+                wikilink,
+            } => vec![
+                ("type", json_string("eqref")),
+                ("label", json_string(label)),
+                ("number", number(*n)),
+                ("wikilink", wikilink.to_string()),
+            ],
+            Inline::Tag(tag) => vec![("type", json_string("tag")), ("tag", json_string(tag))],
+        };
+        node(fields, &inline.span)
+    }
+
+    fn render_inlines(&mut self, inlines: &[InlineToken]) -> String {
+        format!(
+            "[{}]",
+            inlines
+                .iter()
+                .map(|inline| self.render_inline(inline))
+                .join(",")
+        )
+    }
+
+    fn render_block(&mut self, block: &BlockToken) -> String {
+        let fields = match &block.kind {
+            Block::Heading { level, content } => vec![
+                ("type", json_string("heading")),
+                ("level", level.to_string()),
+                ("content", self.render_inlines(content)),
+            ],
+            Block::Plain { content } => vec![
+                ("type", json_string("paragraph")),
+                ("content", self.render_inlines(content)),
+            ],
+            Block::Empty => vec![("type", json_string("break"))],
+            Block::Hr => vec![("type", json_string("hr"))],
+            Block::CodeBlock { lang, info, code } => vec![
+                ("type", json_string("code_block")),
+                ("lang", optional(lang.as_deref())),
+                ("info", json_string(info)),
+                ("code", json_string(code)),
+            ],
+            Block::Quote { content } => vec![
+                ("type", json_string("quote")),
+                ("content", self.render_inlines(content)),
+            ],
+            Block::FootNote { id, content } => vec![
+                ("type", json_string("footnote")),
+                ("id", json_string(id)),
+                ("content", self.render_inlines(content)),
+            ],
+            Block::Latex {
+                tex,
+                label,
+                number: n,
+            } => vec![
+                ("type", json_string("math")),
+                ("tex", json_string(tex)),
+                ("label", optional(label.as_deref())),
+                ("number", number(*n)),
+            ],
+            // 埋め込んだノートのspanはそのノートの中での位置
+            Block::Embed { target, children } => vec![
+                ("type", json_string("embed")),
+                ("target", json_string(target)),
+                ("children", self.render(children)),
+            ],
+            Block::TaskList { items } => {
+                let items = items
+                    .iter()
+                    .map(|item| {
+                        let fields = vec![
+                            ("status", json_string(&item.status.to_string())),
+                            ("closed", is_task_closed(item.status).to_string()),
+                            ("content", self.render_inlines(&item.content)),
+                        ];
+                        node(fields, &item.span)
+                    })
+                    .join(",");
+                vec![
+                    ("type", json_string("task_list")),
+                    ("items", format!("[{items}]")),
+                ]
+            }
+        };
+        node(fields, &block.span)
+    }
+
+    fn render(&mut self, blocks: &[BlockToken]) -> String {
+        format!(
+            "[{}]",
+            blocks
+                .iter()
+                .map(|block| self.render_block(block))
+                .join(",")
+        )
+    }
+}
+
+// パースしたノート全体を出力する
+// {"version", "metadata", "blocks", "links", "diagnostics"}
+pub fn document(note: &ParsedNote, diagnostics: &[Diagnostic]) -> String {
+    let metadata = &note.metadata;
+    let tags = metadata.tags.iter().map(|tag| json_string(tag)).join(",");
+    let metadata = object(vec![
+        ("title", optional(metadata.title.as_deref())),
+        ("tags", format!("[{tags}]")),
+        ("tasks_open", metadata.tasks_open.to_string()),
+        ("tasks_closed", metadata.tasks_closed.to_string()),
+    ]);
+    let diagnostics = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            object(vec![
+                ("severity", json_string("warning")),
+                ("message", json_string(&diagnostic.message)),
+                (
+                    "span",
+                    diagnostic.span.as_ref().map_or("null".to_string(), span),
+                ),
+            ])
+        })
+        .join(",");
+    object(vec![
+        ("version", VERSION.to_string()),
+        ("metadata", metadata),
+        ("blocks", JsonRenderer.render(&note.tokens)),
+        ("links", format!("[{}]", links(&note.tokens).join(","))),
+        ("diagnostics", format!("[{diagnostics}]")),
+    ])
+}
+
+// 本文中のリンクと解決先の一覧。埋め込んだノートの中のリンクは含めない
+fn links(blocks: &[BlockToken]) -> Vec<String> {
+    let mut links = vec![];
+    for block in blocks {
+        if let Block::Embed { target, .. } = &block.kind {
+            let note = target.split(['#', '|']).next().unwrap_or_default().trim();
+            let url = resolver::get().resolve_note(note).map(|link| link.url);
+            links.push(link("embed", target, url.as_deref(), &block.span));
+        }
+        block.walk_inlines(&mut |inline| match &inline.kind {
+            // 定義のない[text]のような参照リンクはurlが空になる
+            Inline::Url { url, .. } => {
+                let resolved = (!url.is_empty()).then_some(url.as_str());
+                links.push(link("url", url, resolved, &inline.span))
+            }
+            Inline::WikiLink { note, url, .. } => {
+                links.push(link("wikilink", note, url.as_deref(), &inline.span))
+            }
+            Inline::Picture { target, src, .. } => {
+                links.push(link("image", target, Some(src), &inline.span))
+            }
+            _ => {}
+        });
+    }
+    links
+}
+
+// urlがnullのものは解決できなかったリンク
+fn link(kind: &str, target: &str, url: Option<&str>, location: &Span) -> String {
+    object(vec![
+        ("kind", json_string(kind)),
+        ("target", json_string(target)),
+        ("url", optional(url)),
+        ("span", span(location)),
+    ])
+}
+
+fn node(mut fields: Vec<(&str, String)>, location: &Span) -> String {
+    fields.push(("span", span(location)));
+    object(fields)
+}
+
+fn span(span: &Span) -> String {
+    object(vec![
+        ("start", position(&span.start)),
+        ("end", position(&span.end)),
+    ])
+}
+
+fn position(position: &Position) -> String {
+    object(vec![
+        ("offset", position.offset.to_string()),
+        ("line", position.line.to_string()),
+        ("column", position.column.to_string()),
+    ])
+}
+
+// 値はJSONに変換済みの文字列
+fn object(fields: Vec<(&str, String)>) -> String {
+    let fields = fields
+        .iter()
+        .map(|(key, value)| format!("{}:{value}", json_string(key)))
+        .join(",");
+    format!("{{{fields}}}")
+}
+
+fn optional(text: Option<&str>) -> String {
+    text.map_or("null".to_string(), json_string)
+}
+
+fn number(n: Option<impl ToString>) -> String {
+    n.map_or("null".to_string(), |n| n.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{build, diagnostics, testing};
+
+    #[test]
+    fn dumps_the_note_with_links_and_diagnostics() {
+        testing::init();
+        let (note, diagnostics) = diagnostics::collect(|| {
+            build::parse_note(
+                Path::new("Note.md"),
+                "# T\n[[Linked]] ![[missing.png]] [not url!!]\n",
+            )
+        });
+        let json = document(&note, &diagnostics);
+        assert!(json.starts_with(&format!("{{\"version\":{VERSION},")));
+        assert!(json.contains(
+            "{\"kind\":\"wikilink\",\"target\":\"Linked\",\"url\":\"https://example.com/linked\""
+        ));
+        assert!(json.contains("{\"kind\":\"url\",\"target\":\"\",\"url\":null"));
+        assert!(json.contains("\"message\":\"image `missing.png` was not found in the vault\""));
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::{
+    asset,
+    build::ParsedNote,
+    metadata::is_task_closed,
+    render::Renderer,
+    token::{Block, BlockToken, Inline, InlineToken},
+};
+
+// トークンをLaTeXに変換する。数式はそのまま出力し、脚注は参照の位置に\footnoteとして展開する
+#[derive(Clone, Debug, Default)]
+pub struct LatexRenderer {
+    footnotes: HashMap<String, Vec<InlineToken>>, // 脚注の定義。参照で使ったものは取り除く
+}
+
+impl LatexRenderer {
+    pub fn new(blocks: &[BlockToken]) -> Self {
+        let mut renderer = Self::default();
+        renderer.collect_footnotes(blocks);
+        renderer
+    }
+
+    // 埋め込んだノートの脚注も含める。同じidが複数ある場合は最初のものを使う
+    fn collect_footnotes(&mut self, blocks: &[BlockToken]) {
+        for block in blocks {
+            match &block.kind {
+                Block::FootNote { id, content } => {
+                    self.footnotes
+                        .entry(id.clone())
+                        .or_insert_with(|| content.clone());
+                }
+                Block::Embed { children, .. } => self.collect_footnotes(children),
+                _ => {}
+            }
+        }
+    }
+
+    fn picture(&mut self, src: &str, target: &str, alt: &str, width: Option<u32>) -> String {
+        // vault内の画像だけを埋め込み、見つからない場合はリンクにする
+        let Some(path) = asset::find_asset(target) else {
+            return format!("\\href{{{}}}{{{}}}", escape_url(src), escape(alt));
+        };
+        let path = path.to_string_lossy().replace('\\', "/");
+        match width {
+            Some(width) => format!("\\includegraphics[width={width}px]{{{path}}}"),
+            None => format!("\\includegraphics[width=\\linewidth,height=\\textheight,keepaspectratio]{{{path}}}"),
+        }
+    }
+}
+
+impl Renderer for LatexRenderer {
+    fn render_inline(&mut self, inline: &InlineToken) -> String {
+        match &inline.kind {
+            Inline::Text(text) => escape(text),
+            Inline::Bold(children) => format!("\\textbf{{{}}}", self.render_inlines(children)),
+            Inline::Code(code) => format!("\\texttt{{{}}}", escape(code)),
+            // \\だと次の行が[で始まる場合にオプションの引数と読まれる
+            Inline::LineBreak => "\\newline\n".to_string(),
+            Inline::Url {
+                url,
+                children,
+                autolink,
+                ..
+            } => {
+                if *autolink {
+                    format!("\\url{{{}}}", escape_url(url))
+                } else if url.is_empty() {
+                    self.render_inlines(children)
+                } else {
+                    let text = self.render_inlines(children);
+                    format!("\\href{{{}}}{{{text}}}", escape_url(url))
+                }
+            }
+            Inline::WikiLink { url, display, .. } => match url {
+                Some(url) => format!("\\href{{{}}}{{{}}}", escape_url(url), escape(display)),
+                None => escape(display),
+            },
+            Inline::FootNote { id } => match self.footnotes.remove(id) {
+                Some(content) => {
+                    format!("\\footnote{{{}}}", self.render_inlines(&content).trim())
+                }
+                None => String::new(),
+            },
+            Inline::Latex { tex, display: true } => display_math(tex, None),
+            Inline::Latex {
+                tex,
+                display: false,
+            } => format!("\\({tex}\\)"),
+            Inline::Picture {
+                target,
+                src,
+                alt,
+                title,
+                width,
+                ..
+            } => {
+                let picture = self.picture(src, target, alt, *width);
+                match title {
+                    Some(title) => format!(
+                        "\\begin{{figure}}[htbp]\n\\centering\n{picture}\n\\caption{{{}}}\n\\end{{figure}}",
+                        escape(title)
+                    ),
+                    None => picture,
+                }
+            }
+            Inline::EqRef {
+                label,
+                number: Some(_),
+                ..
+            } => format!("\\eqref{{{label}}}"),
+            Inline::EqRef {
+                label,
+                wikilink: true,
+                ..
+            } => escape(&format!("#{label}")),
+            Inline::EqRef { .. } => "(??)".to_string(),
+            Inline::Tag(tag) => escape(&format!("#{tag}")),
+        }
+    }
+
+    fn render_block(&mut self, block: &BlockToken) -> String {
+        match &block.kind {
+            Block::Heading { level, content } => {
+                let command = match level {
+                    1 => "section",
+                    2 => "subsection",
+                    _ => "subsubsection",
+                };
+                format!("\\{command}{{{}}}", self.render_inlines(content))
+            }
+            Block::Plain { content } => self.render_inlines(content),
+            Block::Empty => String::new(),
+            Block::Hr => "\\noindent\\rule{\\linewidth}{0.4pt}".to_string(),
+            Block::CodeBlock { lang, code, .. } => {
+                match lang.as_deref().and_then(listings_language) {
+                    Some(language) => format!(
+                        "\\begin{{lstlisting}}[language={language}]\n{code}\n\\end{{lstlisting}}"
+                    ),
+                    None => format!("\\begin{{verbatim}}\n{code}\n\\end{{verbatim}}"),
+                }
+            }
+            Block::Quote { content } => format!(
+                "\\begin{{quote}}\n{}\n\\end{{quote}}",
+                self.render_inlines(content)
+            ),
+            // 脚注は参照の位置に展開する
+            Block::FootNote { .. } => String::new(),
+            Block::Latex { tex, label, .. } => display_math(tex, label.as_deref()),
+            Block::Embed { children, .. } => self.render(children),
+            Block::TaskList { items } => {
+                let items = items
+                    .iter()
+                    .map(|item| {
+                        let mark = if is_task_closed(item.status) {
+                            "$\\boxtimes$"
+                        } else {
+                            "$\\square$"
+                        };
+                        format!("\\item[{mark}] {}", self.render_inlines(&item.content))
+                    })
+                    .join("\n");
+                format!("\\begin{{itemize}}\n{items}\n\\end{{itemize}}")
+            }
+        }
+    }
+
+    // ブロックの間は空行で区切って段落にする
+    fn render(&mut self, blocks: &[BlockToken]) -> String {
+        blocks
+            .iter()
+            .map(|block| self.render_block(block))
+            .filter(|latex| !latex.is_empty())
+            .join("\n\n")
+    }
+}
+
+// ノート全体をコンパイルできるarticleとして出力する
+// 日本語を含むノートはLuaLaTeX(luatexja)で、それ以外はpdfLaTeXでもコンパイルできる
+pub fn document(title: &str, note: &ParsedNote) -> String {
+    let body = LatexRenderer::new(&note.tokens).render(&note.tokens);
+    format!(
+        "\\documentclass{{article}}
+\\usepackage{{iftex}}
+\\ifLuaTeX
+\\usepackage{{luatexja}}
+\\else
+\\usepackage[T1]{{fontenc}}
+\\usepackage[utf8]{{inputenc}}
+\\fi
+\\usepackage{{amsmath,amssymb}}
+\\usepackage{{graphicx}}
+\\usepackage{{listings}}
+\\usepackage{{hyperref}}
+\\lstset{{basicstyle=\\ttfamily\\small,breaklines=true,columns=fullflexible}}
+
+\\title{{{}}}
+\\date{{}}
+
+\\begin{{document}}
+\\maketitle
+
+{body}
+
+\\end{{document}}
+",
+        escape(title)
+    )
+}
+
+// amsmathのディスプレイ数式の環境。\[ \]やequationの中には書けない
+const DISPLAY_ENVIRONMENTS: [&str; 6] = [
+    "equation", "align", "flalign", "alignat", "gather", "multline",
+];
+
+// ディスプレイ数式。数式全体が1つの環境になっている場合はそのまま出力する
+// それ以外は\[ \](ラベルがあればequation)で囲み、中の環境はaligned/gatheredに書き換える
+fn display_math(tex: &str, label: Option<&str>) -> String {
+    let tex = tex.trim();
+    if let Some(name) = top_level_environment(tex) {
+        let end = format!("\\end{{{name}}}");
+        return match (label, tex.strip_suffix(&end)) {
+            // *のついた環境には番号がつかないので、ラベルは番号のつく環境にだけ入れる
+            (Some(label), Some(body)) if !name.ends_with('*') => {
+                format!("{}\n\\label{{{label}}}\n{end}", body.trim_end())
+            }
+            _ => tex.to_string(),
+        };
+    }
+    let tex = inner_environments(tex);
+    match label {
+        Some(label) => {
+            format!("\\begin{{equation}}\n{tex}\n\\label{{{label}}}\n\\end{{equation}}")
+        }
+        None => format!("\\[\n{tex}\n\\]"),
+    }
+}
+
+// 数式全体が1つのディスプレイ数式の環境になっている場合はその名前(*を含む)
+fn top_level_environment(tex: &str) -> Option<&str> {
+    let rest = tex.strip_prefix("\\begin{")?;
+    let name = &rest[..rest.find('}')?];
+    if !DISPLAY_ENVIRONMENTS.contains(&name.trim_end_matches('*')) {
+        return None;
+    }
+    let begin = format!("\\begin{{{name}}}");
+    let end = format!("\\end{{{name}}}");
+    (tex.ends_with(&end) && tex.matches(&begin).count() == 1).then_some(name)
+}
+
+// \[ \]の中に書けるように、ディスプレイ数式の環境を対応する数式の中の環境に置き換える
+fn inner_environments(tex: &str) -> String {
+    let mut tex = tex.to_string();
+    for name in DISPLAY_ENVIRONMENTS {
+        let inner = match name {
+            "align" | "flalign" => "aligned",
+            "alignat" => "alignedat",
+            _ => "gathered",
+        };
+        for name in [format!("{name}*"), name.to_string()] {
+            for command in ["begin", "end"] {
+                tex = tex.replace(
+                    &format!("\\{command}{{{name}}}"),
+                    &format!("\\{command}{{{inner}}}"),
+                );
+            }
+        }
+    }
+    tex
+}
+
+// listingsで定義されている言語の名前。定義されていない言語はverbatimで出力する
+fn listings_language(lang: &str) -> Option<&'static str> {
+    let language = match lang.to_lowercase().as_str() {
+        "c" => "C",
+        "cpp" | "c++" | "cc" => "C++",
+        "java" => "Java",
+        "python" | "py" => "Python",
+        "ruby" | "rb" => "Ruby",
+        "haskell" | "hs" => "Haskell",
+        "bash" | "sh" | "shell" => "bash",
+        "sql" => "SQL",
+        "html" => "HTML",
+        "xml" => "XML",
+        "perl" => "Perl",
+        "lisp" => "Lisp",
+        "ocaml" | "ml" => "[Objective]Caml",
+        "tex" | "latex" => "[LaTeX]TeX",
+        _ => return None,
+    };
+    Some(language)
+}
+
+// テキスト中のLaTeXの特殊文字をエスケープする
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '<' => escaped.push_str("\\textless{}"),
+            '>' => escaped.push_str("\\textgreater{}"),
+            '|' => escaped.push_str("\\textbar{}"),
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// \hrefと\urlの引数では#と%だけをエスケープする
+fn escape_url(url: &str) -> String {
+    let mut escaped = String::new();
+    for c in url.chars() {
+        if matches!(c, '#' | '%') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::block_lexer::BlockLexer, testing};
+
+    #[test]
+    fn renders_sections_escapes_and_equations() {
+        testing::init();
+        let source = [
+            "# Sec",
+            "Hello **b** & 50% $x$",
+            "$$y\\label{e}$$",
+            "see \\eqref{e}",
+        ];
+        let blocks = BlockLexer::new(source.map(String::from).to_vec()).tokenize();
+        assert_eq!(
+            LatexRenderer::new(&blocks).render(&blocks),
+            "\\section{Sec}\n\n\
+             Hello \\textbf{b} \\& 50\\% \\(x\\)\n\n\
+             \\begin{equation}\ny\n\\label{e}\n\\end{equation}\n\n\
+             see \\eqref{e}"
+        );
+    }
+
+    #[test]
+    fn does_not_wrap_display_environments() {
+        assert_eq!(
+            display_math("\\begin{align}\na &= b\n\\end{align}", Some("eq")),
+            "\\begin{align}\na &= b\n\\label{eq}\n\\end{align}"
+        );
+        assert_eq!(
+            display_math("\\begin{gather*}a\\end{gather*}", Some("eq")),
+            "\\begin{gather*}a\\end{gather*}"
+        );
+        assert_eq!(
+            display_math("x = \\begin{align*}a\\end{align*}", None),
+            "\\[\nx = \\begin{aligned}a\\end{aligned}\n\\]"
+        );
+        assert_eq!(
+            display_math("\\begin{alignat}{2}a\\end{alignat} + 1", Some("eq")),
+            "\\begin{equation}\n\\begin{alignedat}{2}a\\end{alignedat} + 1\n\\label{eq}\n\\end{equation}"
+        );
+        assert_eq!(display_math(" x^2 ", None), "\\[\nx^2\n\\]");
+    }
+}
@@ -0,0 +1,414 @@
+use itertools::Itertools;
+
+use crate::{
+    diagnostics,
+    lexer::{block_lexer::BlockLexer, inline_lexer::is_email},
+    link,
+    render::Renderer,
+    resolver,
+    token::{Block, BlockToken, Inline, InlineToken},
+};
+
+// パースしたトークンから正規化したマークダウンを出力する
+// フロントマター、コメント、リンクの定義はトークンに残らないので、トークンの間の元のテキストからそのまま写す
+pub struct MarkdownRenderer {
+    source: String, // spanの基準になる元のテキスト(各行を\nでつないだもの)
+    in_link: bool,  // リンクのテキストの中では対応の取れた[]をエスケープしない
+}
+
+impl MarkdownRenderer {
+    pub fn new(source: String) -> Self {
+        Self {
+            source,
+            in_link: false,
+        }
+    }
+
+    // source[start..end]のうち、行全体が含まれている行。start/endがNoneの場合は先頭/末尾まで
+    fn lines_between(&self, start: Option<usize>, end: Option<usize>) -> Vec<&str> {
+        let text = &self.source[start.unwrap_or(0)..end.unwrap_or(self.source.len())];
+        let mut lines = text.split('\n').collect_vec();
+        // 前後のトークンと同じ行の部分は除く
+        if end.is_some() {
+            lines.pop();
+        }
+        if start.is_some() && !lines.is_empty() {
+            lines.remove(0);
+        }
+        lines
+    }
+
+    // 続けて書かれた行をまとめる。空行は区切りとして捨てる
+    fn chunks_between(&self, start: Option<usize>, end: Option<usize>) -> Vec<String> {
+        self.lines_between(start, end)
+            .split(|line| line.trim().is_empty())
+            .filter(|chunk| !chunk.is_empty())
+            .map(|chunk| chunk.join("\n"))
+            .collect()
+    }
+
+    fn inline_math(tex: &str, followed_by_digit: bool) -> String {
+        // $の直後と直前が空白でなく、直後が数字でない場合だけ$で囲める
+        let dollar = !tex.is_empty()
+            && !tex.starts_with(char::is_whitespace)
+            && !tex.ends_with(char::is_whitespace)
+            && !tex.contains('$')
+            && !followed_by_digit;
+        if dollar {
+            format!("${tex}$")
+        } else {
+            format!("\\({tex}\\)")
+        }
+    }
+}
+
+impl Renderer for MarkdownRenderer {
+    fn render_inline(&mut self, inline: &InlineToken) -> String {
+        match &inline.kind {
+            Inline::Text(text) => escape(text, self.in_link),
+            Inline::Bold(children) => format!("**{}**", self.render_inlines(children)),
+            Inline::Code(code) => format!("`{code}`"),
+            Inline::LineBreak => {
+                // 段落の途中の空行とコメントなどの行は残す
+                let mut text = "\n".to_string();
+                let (start, end) = (inline.span.start.offset, inline.span.end.offset);
+                let mut blank = false;
+                for line in self.lines_between(Some(start), Some(end)) {
+                    if line.trim().is_empty() {
+                        if !blank {
+                            text.push('\n');
+                        }
+                        blank = true;
+                    } else {
+                        text.push_str(line);
+                        text.push('\n');
+                        blank = false;
+                    }
+                }
+                text
+            }
+            Inline::Url {
+                url,
+                title,
+                children,
+                autolink,
+            } => {
+                if *autolink {
+                    let text = children.iter().map(plain_text).join("");
+                    // www.で始まるものは<>で囲むと自動リンクにならない
+                    return if link::scheme(&text).is_some() || is_email(&text) {
+                        format!("<{text}>")
+                    } else {
+                        text
+                    };
+                }
+                self.in_link = true;
+                let text = self.render_inlines(children);
+                self.in_link = false;
+                if url.is_empty() {
+                    return format!("[{text}]");
+                }
+                let url = destination(url);
+                match title {
+                    Some(title) => {
+                        let title = match (title.contains('"'), title.contains('\'')) {
+                            (false, _) => format!("\"{title}\""),
+                            (true, false) => format!("'{title}'"),
+                            (true, true) => format!("({title})"),
+                        };
+                        format!("[{text}]({url} {title})")
+                    }
+                    None => format!("[{text}]({url})"),
+                }
+            }
+            Inline::WikiLink {
+                note,
+                section,
+                alias,
+                embed,
+                ..
+            } => {
+                let mut link = note.clone();
+                if let Some(section) = section {
+                    link.push_str(&format!("#{section}"));
+                }
+                if let Some(alias) = alias {
+                    link.push_str(&format!("|{alias}"));
+                }
+                let bang = if *embed { "!" } else { "" };
+                format!("{bang}[[{link}]]")
+            }
+            Inline::FootNote { id } => format!("[^{id}]"),
+            Inline::Latex { tex, display: true } if !tex.contains('$') && !tex.is_empty() => {
+                format!("$${tex}$$")
+            }
+            Inline::Latex { tex, display: true } => format!("\\[{tex}\\]"),
+            Inline::Latex {
+                tex,
+                display: false,
+            } => Self::inline_math(tex, false),
+            Inline::Picture {
+                target,
+                alt,
+                title,
+                width,
+                height,
+                wikilink: true,
+                ..
+            } => {
+                let mut link = target.clone();
+                if alt != target {
+                    link.push_str(&format!("|{alt}"));
+                }
+                match (width, height) {
+                    (Some(width), Some(height)) => link.push_str(&format!("|{width}x{height}")),
+                    (Some(width), None) => link.push_str(&format!("|{width}")),
+                    _ => {}
+                }
+                if title.is_some() {
+                    diagnostics::warn(format!(
+                        "the title of `{target}` cannot be written in ![[]]"
+                    ));
+                }
+                format!("![[{link}]]")
+            }
+            Inline::Picture {
+                target, alt, title, ..
+            } => {
+                let target = destination(target);
+                match title {
+                    Some(title) => format!("![{alt}]({target} \"{title}\")"),
+                    None => format!("![{alt}]({target})"),
+                }
+            }
+            Inline::EqRef {
+                label,
+                wikilink: true,
+                ..
+            } => format!("[[#{label}]]"),
+            Inline::EqRef { label, .. } => format!("\\eqref{{{label}}}"),
+            Inline::Tag(tag) => format!("#{tag}"),
+        }
+    }
+
+    fn render_inlines(&mut self, inlines: &[InlineToken]) -> String {
+        let mut text = String::new();
+        for (i, inline) in inlines.iter().enumerate() {
+            match &inline.kind {
+                Inline::Latex {
+                    tex,
+                    display: false,
+                } => {
+                    // $x$1 は数式にならないので後ろに数字が続く場合は\( \)にする
+                    let followed_by_digit = matches!(
+                        inlines.get(i + 1).map(|tk| &tk.kind),
+                        Some(Inline::Text(next)) if next.starts_with(|c: char| c.is_ascii_digit())
+                    );
+                    text.push_str(&Self::inline_math(tex, followed_by_digit));
+                }
+                _ => text.push_str(&self.render_inline(inline)),
+            }
+        }
+        text
+    }
+
+    fn render_block(&mut self, block: &BlockToken) -> String {
+        match &block.kind {
+            Block::Heading { level, content } => {
+                format!("{} {}", "#".repeat(*level), self.render_inlines(content))
+            }
+            Block::Plain { content } => self
+                .render_inlines(content)
+                .split('\n')
+                .map(|line| {
+                    // 行頭に来ると別のブロックになってしまう文字
+                    if line.starts_with('>') || line.starts_with("---") {
+                        format!("\\{line}")
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .join("\n"),
+            Block::Empty => String::new(),
+            Block::Hr => "---".to_string(),
+            Block::CodeBlock { info, code, .. } => {
+                if code.is_empty() {
+                    format!("```{info}\n```")
+                } else {
+                    format!("```{info}\n{code}\n```")
+                }
+            }
+            Block::Quote { content } => self
+                .render_inlines(content)
+                .split('\n')
+                .map(|line| format!("> {line}").trim_end().to_string())
+                .join("\n"),
+            Block::FootNote { id, content } => {
+                format!("[^{id}]: {}", self.render_inlines(content).trim_start())
+            }
+            Block::Latex { tex, label, .. } => {
+                let label = label
+                    .as_ref()
+                    .map(|label| format!("\\label{{{label}}}"))
+                    .unwrap_or_default();
+                format!("$$\n{}{label}\n$$", tex.trim())
+            }
+            Block::Embed { target, .. } => format!("![[{target}]]"),
+            Block::TaskList { items } => items
+                .iter()
+                .map(|item| {
+                    let text = self.render_inlines(&item.content);
+                    format!("- [{}] {text}", item.status).trim_end().to_string()
+                })
+                .join("\n"),
+        }
+    }
+
+    // ブロックの間は空行1つにする。Empty(2行以上の空行)は空行2つにする
+    // トークンに含まれない行は空行の数が変わらないように、次のブロックの直前に空行を挟まずに置く
+    fn render(&mut self, blocks: &[BlockToken]) -> String {
+        let mut pieces = vec![];
+        let mut pending = vec![];
+        let mut blank_lines = 1;
+        let mut previous_end = None;
+        for block in blocks {
+            pending.extend(self.chunks_between(previous_end, Some(block.span.start.offset)));
+            previous_end = Some(block.span.end.offset);
+            if let Block::Empty = block.kind {
+                // 空行の間にあったリンクの定義の行はEmptyのspanに含まれる
+                let start = block.span.start.offset.checked_sub(1);
+                let end = Some(block.span.end.offset + 1).filter(|&end| end <= self.source.len());
+                pending.extend(self.chunks_between(start, end));
+                blank_lines = 2;
+                continue;
+            }
+            pending.push(self.render_block(block));
+            pieces.push((pending.join("\n"), blank_lines));
+            pending.clear();
+            blank_lines = 1;
+        }
+        pending.extend(self.chunks_between(previous_end, None));
+        if !pending.is_empty() {
+            pieces.push((pending.join("\n"), blank_lines));
+        }
+
+        let mut text = String::new();
+        for (i, (piece, blank_lines)) in pieces.into_iter().enumerate() {
+            if i > 0 {
+                text.push_str(&"\n".repeat(blank_lines + 1));
+            }
+            text.push_str(&piece);
+        }
+        text.push('\n');
+        text
+    }
+}
+
+// ノートを整形したマークダウンを返す
+// 書かれた内容だけを扱うので、vaultは参照せず、埋め込みの展開や画像のコピーも行わない
+pub fn format(content: &str) -> String {
+    let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+    let source = lines.join("\n");
+    let tokens = resolver::detached(|| BlockLexer::new(lines).tokenize());
+    MarkdownRenderer::new(source).render(&tokens)
+}
+
+// 書かれたままだと記法として読まれてしまう文字をエスケープする
+// ]は\]にすると\[ \]の数式と読まれることがあるのでエスケープしない
+fn escape(text: &str, in_link: bool) -> String {
+    let chars = text.chars().collect_vec();
+    let mut escaped = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|j| chars[j]);
+        let special = match c {
+            '\\' | '`' | '$' => true,
+            '[' => !in_link,
+            '*' => prev == Some('*') || chars.get(i + 1) == Some(&'*'),
+            // 見出しやタグと読まれる#はエスケープする。見出しにならない4つ以上の#と空白はそのまま残す
+            '#' => {
+                let hashes = chars[i..].iter().take_while(|&&c| c == '#').count();
+                prev.is_none_or(|p| p.is_whitespace())
+                    && !(hashes >= 4 && chars.get(i + hashes) == Some(&' '))
+            }
+            '<' => {
+                let inner = chars[i + 1..]
+                    .iter()
+                    .take_while(|&&c| c != '>' && !c.is_whitespace())
+                    .join("");
+                link::scheme(&inner).is_some() || is_email(&inner)
+            }
+            _ => false,
+        };
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn plain_text(inline: &InlineToken) -> String {
+    match &inline.kind {
+        Inline::Text(text) => text.clone(),
+        _ => String::new(),
+    }
+}
+
+// 空白を含むか括弧の対応が取れていないURLは<>で囲む
+fn destination(url: &str) -> String {
+    if url.contains(char::is_whitespace) || !balanced(url) {
+        format!("<{url}>")
+    } else {
+        url.to_string()
+    }
+}
+
+fn balanced(url: &str) -> bool {
+    let mut depth = 0;
+    for c in url.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return false,
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asset, testing};
+
+    #[test]
+    fn normalizes_and_keeps_the_result_stable() {
+        testing::init();
+        let formatted =
+            format("# Title\ntext with **bold** and $x$\n[link]( https://example.com  \"t\" )\n");
+        assert_eq!(
+            formatted,
+            "# Title\n\ntext with **bold** and $x$\n[link](https://example.com \"t\")\n"
+        );
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn formats_without_reading_the_vault() {
+        testing::init();
+        let output = testing::output_dir("fmt");
+        let content = "![[Linked]]\n![[Missing]]\n\n![[pic.png|300]]\n";
+        let (formatted, diagnostics) =
+            diagnostics::collect(|| asset::with_output(&output, || format(content)));
+        assert_eq!(formatted, content);
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn keeps_deeper_headings_as_written() {
+        testing::init();
+        let content = "#### four\n\n###### six #tag\n";
+        assert_eq!(format(content), content);
+    }
+}
@@ -0,0 +1,66 @@
+pub mod html;
+pub mod json;
+pub mod latex;
+pub mod markdown;
+pub mod text;
+
+use itertools::Itertools;
+
+use crate::token::{BlockToken, InlineToken};
+
+// トークン列を出力の形式に変換する
+// 子要素を持つノードの実装は子要素ごとにrender_block/render_inlineを呼び直すので、
+// 一部のノードだけを変えたい場合はそのノードだけを処理し、残りを既存の実装(html::render_blockなど)に任せればよい
+pub trait Renderer {
+    fn render_block(&mut self, block: &BlockToken) -> String;
+    fn render_inline(&mut self, inline: &InlineToken) -> String;
+
+    fn render_inlines(&mut self, inlines: &[InlineToken]) -> String {
+        inlines
+            .iter()
+            .map(|inline| self.render_inline(inline))
+            .join("")
+    }
+
+    fn render(&mut self, blocks: &[BlockToken]) -> String {
+        blocks
+            .iter()
+            .map(|block| self.render_block(block))
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::block_lexer::BlockLexer, testing, token::Block};
+
+    // 見出しだけを変え、残りはHTMLの実装に任せる
+    struct AnchoredHeadings;
+
+    impl Renderer for AnchoredHeadings {
+        fn render_block(&mut self, block: &BlockToken) -> String {
+            match &block.kind {
+                Block::Heading { content, .. } => {
+                    format!("<h2 id=\"h\">{}</h2>", self.render_inlines(content))
+                }
+                _ => html::render_block(self, block),
+            }
+        }
+
+        fn render_inline(&mut self, inline: &InlineToken) -> String {
+            html::render_inline(self, inline)
+        }
+    }
+
+    #[test]
+    fn overrides_only_some_nodes() {
+        testing::init();
+        let blocks =
+            BlockLexer::new(vec!["# **Title**".to_string(), "> quote".to_string()]).tokenize();
+        assert_eq!(
+            AnchoredHeadings.render(&blocks),
+            "<h2 id=\"h\"><strong>Title</strong></h2>\n<blockquote>quote</blockquote>"
+        );
+    }
+}
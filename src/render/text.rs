@@ -0,0 +1,138 @@
+use itertools::Itertools;
+
+use crate::{
+    render::Renderer,
+    token::{Block, BlockToken, Inline, InlineToken},
+};
+
+// 記法を取り除いたテキスト。検索やOpenGraphのdescriptionに使う
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextRenderer {
+    pub keep_math: bool, // 数式のTeXを残す
+    pub keep_code: bool, // インラインコードとコードブロックを残す
+}
+
+impl Renderer for TextRenderer {
+    fn render_inline(&mut self, inline: &InlineToken) -> String {
+        match &inline.kind {
+            Inline::Text(text) => text.clone(),
+            Inline::Bold(children) | Inline::Url { children, .. } => self.render_inlines(children),
+            Inline::Code(code) if self.keep_code => code.clone(),
+            Inline::Latex { tex, .. } if self.keep_math => tex.clone(),
+            Inline::Code(_) | Inline::Latex { .. } => String::new(),
+            Inline::LineBreak => "\n".to_string(),
+            Inline::WikiLink { display, .. } => display.clone(),
+            Inline::FootNote { .. } => String::new(),
+            Inline::Picture { alt, .. } => alt.clone(),
+            Inline::EqRef {
+                number: Some(number),
+                ..
+            } => format!("({number})"),
+            Inline::EqRef { .. } => String::new(),
+            Inline::Tag(tag) => format!("#{tag}"),
+        }
+    }
+
+    fn render_block(&mut self, block: &BlockToken) -> String {
+        match &block.kind {
+            Block::Heading { content, .. }
+            | Block::Plain { content }
+            | Block::Quote { content }
+            | Block::FootNote { content, .. } => self.render_inlines(content).trim().to_string(),
+            Block::CodeBlock { code, .. } if self.keep_code => code.clone(),
+            Block::Latex { tex, .. } if self.keep_math => tex.clone(),
+            Block::Empty | Block::Hr | Block::CodeBlock { .. } | Block::Latex { .. } => {
+                String::new()
+            }
+            Block::Embed { children, .. } => self.render(children),
+            Block::TaskList { items } => items
+                .iter()
+                .map(|item| self.render_inlines(&item.content).trim().to_string())
+                .join("\n"),
+        }
+    }
+
+    // 空のブロックは飛ばし、ブロックの間は空行で区切る
+    fn render(&mut self, blocks: &[BlockToken]) -> String {
+        blocks
+            .iter()
+            .map(|block| self.render_block(block))
+            .filter(|text| !text.is_empty())
+            .join("\n\n")
+    }
+}
+
+// 本文(見出し・脚注・埋め込みを除く)の先頭から最大length文字を、できるだけ文の区切りで切って返す
+pub fn summary(blocks: &[BlockToken], length: usize) -> String {
+    let mut renderer = TextRenderer::default();
+    let body = blocks
+        .iter()
+        .filter(|block| {
+            matches!(
+                block.kind,
+                Block::Plain { .. } | Block::Quote { .. } | Block::TaskList { .. }
+            )
+        })
+        .map(|block| renderer.render_block(block))
+        .join(" ");
+    let chars = body.split_whitespace().join(" ").chars().collect_vec();
+    if chars.len() <= length {
+        return chars.iter().collect();
+    }
+
+    let is_sentence_end = |i: usize| match chars[i] {
+        '。' | '．' | '！' | '？' | '!' | '?' => true,
+        // 3.14のような数字の中の.は区切りとみなさない
+        '.' => chars.get(i + 1).is_none_or(|c| c.is_whitespace()),
+        _ => false,
+    };
+    if let Some(end) = (0..length).rev().find(|&i| is_sentence_end(i)) {
+        return chars[..=end].iter().collect();
+    }
+    // 文の区切りがなければ単語の区切り、それもなければ文字数で切る。…を含めてlength文字に収める
+    let limit = length.saturating_sub(1);
+    let end = (1..=limit)
+        .rev()
+        .find(|&i| chars[i].is_whitespace())
+        .unwrap_or(limit);
+    format!("{}…", chars[..end].iter().collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::block_lexer::BlockLexer, testing};
+
+    fn summarize(source: &str, length: usize) -> String {
+        testing::init();
+        let lines = source.lines().map(|line| line.to_string()).collect();
+        summary(&BlockLexer::new(lines).tokenize(), length)
+    }
+
+    #[test]
+    fn summarizes_at_sentence_boundaries() {
+        let source = "# Heading\nHello **world**. It costs 3.14 `code` dollars today\n";
+        assert_eq!(summarize(source, 20), "Hello world.");
+        // 文の区切りがなければ単語の区切りで切る。3.14の.は区切りではない
+        assert_eq!(
+            summarize("It costs 3.14 `code` dollars today\n", 22),
+            "It costs 3.14 dollars…"
+        );
+        assert_eq!(summarize("short", 120), "short");
+        // 区切りがなければ…を含めてlength文字にする
+        assert_eq!(summarize("abcdefghij", 5), "abcd…");
+        assert_eq!(summarize("abcd efgh", 5), "abcd…");
+    }
+
+    #[test]
+    fn keeps_math_and_code_when_asked() {
+        testing::init();
+        let blocks = BlockLexer::new(vec!["$x$ and `y`".to_string()]).tokenize();
+        assert_eq!(TextRenderer::default().render(&blocks), "and");
+        let mut renderer = TextRenderer {
+            keep_math: true,
+            keep_code: true,
+        };
+        assert_eq!(renderer.render(&blocks), "x and y");
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    cell::Cell,
+    fs::File,
+    io::{BufRead, BufReader},
+    sync::OnceLock,
+};
+
+use crate::{asset, util::get_path};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResolvedLink {
+    pub url: String,
+    pub anchor: Option<String>, // ページ内の位置。[[Note#Section]]の指定があればそちらが優先される
+    pub title: Option<String>,  // 別名の指定がない場合に表示するテキスト
+}
+
+// [[Note]] や ![[image.png]] の参照先を決める
+// ノートの対応付けを変えたい場合はこれを実装してset_resolverで登録する
+pub trait LinkResolver: Send + Sync {
+    fn resolve_note(&self, name: &str) -> Option<ResolvedLink>;
+    fn resolve_asset(&self, name: &str) -> Option<String>;
+}
+
+// デフォルトの実装。vault内のノートの1行目にある <!-- url: ... --> をURLとして使う
+pub struct VaultResolver;
+
+impl LinkResolver for VaultResolver {
+    fn resolve_note(&self, name: &str) -> Option<ResolvedLink> {
+        let file = File::open(get_path(name.to_string())?).ok()?;
+        let line = BufReader::new(file).lines().next()?.ok()?;
+        if !line.starts_with("<!-- url: ") || !line.trim().ends_with("-->") {
+            return None;
+        }
+
+        let url = line
+            .trim_start_matches("<!-- url:")
+            .trim_end_matches("-->")
+            .trim()
+            .to_string();
+        if url.is_empty() {
+            return None;
+        }
+        Some(ResolvedLink {
+            url,
+            ..Default::default()
+        })
+    }
+
+    fn resolve_asset(&self, name: &str) -> Option<String> {
+        asset::vault_asset_url(name)
+    }
+}
+
+// vaultを参照しない場合の実装。ノートは解決せず、画像は書かれたままの名前にする
+struct SourceResolver;
+
+impl LinkResolver for SourceResolver {
+    fn resolve_note(&self, _name: &str) -> Option<ResolvedLink> {
+        None
+    }
+
+    fn resolve_asset(&self, name: &str) -> Option<String> {
+        Some(name.to_string())
+    }
+}
+
+static RESOLVER: OnceLock<Box<dyn LinkResolver>> = OnceLock::new();
+
+thread_local! {
+    // trueの間はvaultを参照しない
+    static DETACHED: Cell<bool> = const { Cell::new(false) };
+}
+
+// 最初のリンクの解決より前に呼ぶ必要がある。既に決まっている場合は渡されたものを返す
+pub fn set_resolver(resolver: Box<dyn LinkResolver>) -> Result<(), Box<dyn LinkResolver>> {
+    RESOLVER.set(resolver)
+}
+
+pub fn get() -> &'static dyn LinkResolver {
+    if is_detached() {
+        return &SourceResolver;
+    }
+    RESOLVER.get_or_init(|| Box::new(VaultResolver)).as_ref()
+}
+
+// vaultを参照せずにfを実行する。リンクや画像は解決せず、![[Note]]も展開しないので、画像のコピーなども起きない
+// 書かれた内容だけが必要な場合(整形やリンクの関係を求める場合)に使う
+pub fn detached<T>(f: impl FnOnce() -> T) -> T {
+    let previous = DETACHED.with(|detached| detached.replace(true));
+    let result = f();
+    DETACHED.with(|detached| detached.set(previous));
+    result
+}
+
+pub fn is_detached() -> bool {
+    DETACHED.with(|detached| detached.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn resolves_notes_from_the_url_comment() {
+        testing::init();
+        let link = get().resolve_note("Linked").unwrap();
+        assert_eq!(link.url, "https://example.com/linked");
+        assert_eq!(get().resolve_note("Missing"), None);
+        assert_eq!(
+            get().resolve_asset("pic.png").as_deref(),
+            Some("/assets/pictures/pic.png")
+        );
+        // vaultを参照しない間は解決しない
+        assert_eq!(detached(|| get().resolve_note("Linked")), None);
+        assert_eq!(
+            detached(|| get().resolve_asset("pic.png")).as_deref(),
+            Some("pic.png")
+        );
+    }
+}
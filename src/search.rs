@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+
+use crate::{
+    render::{text::TextRenderer, Renderer},
+    token::{Block, BlockToken},
+    util::json_string,
+};
+
+// 出力するJSONの形式の版
+pub const VERSION: u32 = 1;
+// 日本語などの単語の区切りがない文字列を分割する文字数
+pub const NGRAM: usize = 2;
+
+// フィールドごとの重み。タイトルや見出しに含まれる語を本文より優先する
+const TITLE_WEIGHT: usize = 10;
+const HEADING_WEIGHT: usize = 5;
+const TAG_WEIGHT: usize = 5;
+const BODY_WEIGHT: usize = 1;
+
+struct Document {
+    title: String,
+    url: String,
+    headings: Vec<String>,
+    tags: Vec<String>,
+    body: String,
+}
+
+// クライアント側で全文検索するための転置インデックス。vault全体の変換時にsearch.jsonとして出力する
+#[derive(Default)]
+pub struct SearchIndex {
+    documents: Vec<Document>,
+    terms: BTreeMap<String, BTreeMap<usize, usize>>, // 語 -> (ノートの番号 -> 重み)
+}
+
+impl SearchIndex {
+    pub fn add(&mut self, title: &str, url: &str, tags: &[String], blocks: &[BlockToken]) {
+        let mut headings = vec![];
+        let mut body = vec![];
+        collect_text(
+            blocks,
+            &mut TextRenderer::default(),
+            &mut headings,
+            &mut body,
+        );
+        let document = Document {
+            title: title.to_string(),
+            url: url.to_string(),
+            headings,
+            tags: tags.to_vec(),
+            body: body.join("\n"),
+        };
+
+        let id = self.documents.len();
+        let mut add_terms = |text: &str, weight: usize| {
+            for term in tokenize(text) {
+                *self.terms.entry(term).or_default().entry(id).or_default() += weight;
+            }
+        };
+        add_terms(&document.title, TITLE_WEIGHT);
+        document
+            .headings
+            .iter()
+            .for_each(|heading| add_terms(heading, HEADING_WEIGHT));
+        document
+            .tags
+            .iter()
+            .for_each(|tag| add_terms(tag, TAG_WEIGHT));
+        add_terms(&document.body, BODY_WEIGHT);
+        self.documents.push(document);
+    }
+
+    // {"version", "ngram", "documents": [{title, url, headings, tags, body}], "index": {語: [[番号, 重み]]}}
+    pub fn to_json(&self) -> String {
+        let strings =
+            |items: &[String]| format!("[{}]", items.iter().map(|s| json_string(s)).join(","));
+        let documents = self
+            .documents
+            .iter()
+            .map(|document| {
+                format!(
+                    "    {{\"title\": {}, \"url\": {}, \"headings\": {}, \"tags\": {}, \"body\": {}}}",
+                    json_string(&document.title),
+                    json_string(&document.url),
+                    strings(&document.headings),
+                    strings(&document.tags),
+                    json_string(&document.body)
+                )
+            })
+            .join(",\n");
+        let index = self
+            .terms
+            .iter()
+            .map(|(term, postings)| {
+                let postings = postings
+                    .iter()
+                    .map(|(id, weight)| format!("[{id},{weight}]"))
+                    .join(",");
+                format!("    {}: [{postings}]", json_string(term))
+            })
+            .join(",\n");
+        format!(
+            "{{\n  \"version\": {VERSION},\n  \"ngram\": {NGRAM},\n  \"documents\": [\n{documents}\n  ],\n  \"index\": {{\n{index}\n  }}\n}}\n"
+        )
+    }
+}
+
+// 見出しとそれ以外の本文のテキストを分けて集める。埋め込んだノートの中身も含める
+fn collect_text(
+    blocks: &[BlockToken],
+    renderer: &mut TextRenderer,
+    headings: &mut Vec<String>,
+    body: &mut Vec<String>,
+) {
+    for block in blocks {
+        match &block.kind {
+            Block::Heading { content, .. } => {
+                headings.push(renderer.render_inlines(content).trim().to_string())
+            }
+            Block::Embed { children, .. } => collect_text(children, renderer, headings, body),
+            _ => {
+                let text = renderer.render_block(block);
+                if !text.is_empty() {
+                    body.push(text);
+                }
+            }
+        }
+    }
+}
+
+// 検索語に分割する。英数字は小文字にした単語、それ以外の文字(日本語など)が続く部分はNGRAM文字ずつずらした文字列にする
+// クライアント側でも検索する文字列を同じ規則で分割する
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = vec![];
+    let text = text.to_lowercase();
+    let chars = text.chars().collect_vec();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if !c.is_alphanumeric() {
+            i += 1;
+            continue;
+        }
+        let word = c.is_ascii_alphanumeric();
+        let end = (i..chars.len())
+            .find(|&j| !chars[j].is_alphanumeric() || chars[j].is_ascii_alphanumeric() != word)
+            .unwrap_or(chars.len());
+        let run = &chars[i..end];
+        if word || run.len() <= NGRAM {
+            terms.push(run.iter().collect());
+        } else {
+            terms.extend(run.windows(NGRAM).map(|gram| gram.iter().collect()));
+        }
+        i = end;
+    }
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::block_lexer::BlockLexer, testing};
+
+    #[test]
+    fn indexes_words_and_ngrams() {
+        assert_eq!(tokenize("Rust 日本語"), ["rust", "日本", "本語"]);
+
+        testing::init();
+        let blocks =
+            BlockLexer::new(vec!["## Usage".to_string(), "rust body".to_string()]).tokenize();
+        let mut index = SearchIndex::default();
+        index.add("Rust", "/rust.html", &["lang".to_string()], &blocks);
+        let json = index.to_json();
+        assert!(json.contains("\"headings\": [\"Usage\"]"));
+        // タイトルと本文の両方に含まれる語は重みを足す
+        assert!(json.contains(&format!("\"rust\": [[0,{}]]", TITLE_WEIGHT + BODY_WEIGHT)));
+    }
+}
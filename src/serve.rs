@@ -0,0 +1,358 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    thread,
+    time::UNIX_EPOCH,
+};
+
+use itertools::Itertools;
+
+use crate::{
+    asset, build,
+    graph::LinkGraph,
+    resolver::{self, LinkResolver, ResolvedLink},
+    template,
+    util::{escape_html, get_path},
+};
+
+// プレビュー用のリンクの解決。ノートと画像をこのサーバのURLにする
+// 相対リンクの書き換え(ARMP_LINK_BASE_URLなど)を受けないようにスキームから書く
+struct PreviewResolver {
+    origin: String,
+    root: &'static Path,
+}
+
+impl LinkResolver for PreviewResolver {
+    fn resolve_note(&self, name: &str) -> Option<ResolvedLink> {
+        let path = get_path(name.to_string())?;
+        Some(ResolvedLink {
+            url: format!(
+                "{}{}",
+                self.origin,
+                percent_encode(&note_path(self.root, &path))
+            ),
+            ..Default::default()
+        })
+    }
+
+    fn resolve_asset(&self, name: &str) -> Option<String> {
+        asset::find_asset(name)?;
+        Some(format!("{}/assets/{}", self.origin, percent_encode(name)))
+    }
+}
+
+// プレビューのページのURLの先頭
+pub fn origin(port: u16) -> String {
+    format!("http://127.0.0.1:{port}")
+}
+
+// localhostでvaultをプレビューする。ノートはリクエストのたびに変換する
+pub fn serve(root: &'static Path, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let origin = origin(port);
+    if resolver::set_resolver(Box::new(PreviewResolver {
+        origin: origin.clone(),
+        root,
+    }))
+    .is_err()
+    {
+        return Err(io::Error::other("the link resolver is already set"));
+    }
+    eprintln!("Serving {} at {origin}/", root.display());
+    for stream in listener.incoming() {
+        let stream = stream?;
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, root) {
+                eprintln!("Warning: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, root: &Path) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // ヘッダは使わないが、読まずに閉じると応答が届かないことがあるので読み捨てる
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, 400, "text/plain", b"Bad Request");
+    };
+    if method != "GET" {
+        return respond(&mut stream, 405, "text/plain", b"Method Not Allowed");
+    }
+
+    let path = percent_decode(target.split(['?', '#']).next().unwrap_or_default());
+    if path == "/" {
+        return respond(
+            &mut stream,
+            200,
+            "text/html; charset=utf-8",
+            index(root).as_bytes(),
+        );
+    }
+    if let Some(name) = path.strip_prefix("/assets/") {
+        return match asset_path(root, name).map(fs::read) {
+            Some(Ok(bytes)) => respond(&mut stream, 200, content_type(name), &bytes),
+            _ => not_found(&mut stream),
+        };
+    }
+    // ライブリロード用に変換元のファイルの更新日時を返す
+    if let Some(rest) = path.strip_prefix("/__stamp") {
+        return match source_path(root, rest) {
+            Some(source) => respond(&mut stream, 200, "text/plain", stamp(&source).as_bytes()),
+            None => not_found(&mut stream),
+        };
+    }
+    match source_path(root, &path) {
+        Some(source) => {
+            let html = render(&source, &path)?;
+            respond(
+                &mut stream,
+                200,
+                "text/html; charset=utf-8",
+                html.as_bytes(),
+            )
+        }
+        None => not_found(&mut stream),
+    }
+}
+
+fn render(source: &Path, path: &str) -> io::Result<String> {
+    let content = fs::read_to_string(source)?;
+    let graph = template::uses("backlinks").then(LinkGraph::from_vault);
+    let note = build::render_note(source, &content, graph.as_ref());
+    let script = reload_script(path, &stamp(source));
+    let html = match note.html.rfind("</body>") {
+        Some(i) => format!("{}{script}{}", &note.html[..i], &note.html[i..]),
+        None => format!("{}\n{script}", note.html),
+    };
+    Ok(html)
+}
+
+// 変換元のファイルの更新日時が変わったらページを読み込み直す
+fn reload_script(path: &str, stamp: &str) -> String {
+    format!(
+        "<script>
+(() => {{
+  let stamp = \"{stamp}\";
+  setInterval(async () => {{
+    try {{
+      const res = await fetch(\"/__stamp{}\", {{ cache: \"no-store\" }});
+      if (!res.ok) return;
+      const next = await res.text();
+      if (next !== stamp) location.reload();
+      stamp = next;
+    }} catch (e) {{}}
+  }}, 1000);
+}})();
+</script>
+",
+        percent_encode(path)
+    )
+}
+
+fn stamp(source: &Path) -> String {
+    fs::metadata(source)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(String::new(), |duration| duration.as_nanos().to_string())
+}
+
+// すべてのノートへのリンクの一覧
+fn index(root: &Path) -> String {
+    let graph = LinkGraph::from_vault();
+    let items = graph
+        .notes()
+        .map(|(name, path)| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                escape_html(&percent_encode(&note_path(root, path))),
+                escape_html(name)
+            )
+        })
+        .join("\n");
+    format!(
+        "<!DOCTYPE html>\n<meta charset=\"utf-8\">\n<title>armp</title>\n<ul>\n{items}\n</ul>\n"
+    )
+}
+
+// vault内のノートのプレビューのパス。vaultと同じディレクトリ構成で拡張子を.htmlにする
+fn note_path(root: &Path, note: &Path) -> String {
+    let relative = note.strip_prefix(root).unwrap_or(note);
+    format!(
+        "/{}",
+        relative
+            .with_extension("html")
+            .to_string_lossy()
+            .replace('\\', "/")
+    )
+}
+
+// /folder/Note.html に対応する vault/folder/Note.md。vaultの外を指すパスは受け付けない
+fn source_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/')).with_extension("md");
+    if !path.ends_with(".html")
+        || relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let source = root.join(relative);
+    source.is_file().then_some(source)
+}
+
+// /assets/以下で返すファイル。シンボリックリンクなどでvaultの外を指すものは返さない
+fn asset_path(root: &Path, name: &str) -> Option<PathBuf> {
+    if !asset::is_relative_name(name) {
+        return None;
+    }
+    let path = asset::find_asset(name)?.canonicalize().ok()?;
+    let root = root.canonicalize().ok()?;
+    path.starts_with(root).then_some(path)
+}
+
+fn content_type(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("bmp") => "image/bmp",
+        Some("avif") => "image/avif",
+        _ => "application/octet-stream",
+    }
+}
+
+fn not_found(stream: &mut TcpStream) -> io::Result<()> {
+    respond(stream, 404, "text/plain", b"Not Found")
+}
+
+fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Method Not Allowed",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// URLのパスとして使えない文字を%エンコードする。/はそのまま残す
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn maps_preview_paths_to_notes() {
+        let vault = testing::init();
+        assert_eq!(
+            source_path(vault, "/Linked.html"),
+            Some(vault.join("Linked.md"))
+        );
+        assert_eq!(source_path(vault, "/Linked.md"), None);
+        assert_eq!(
+            note_path(vault, &vault.join("a/README.md")),
+            "/a/README.html"
+        );
+        assert_eq!(
+            percent_encode("/a b/ノート.html"),
+            "/a%20b/%E3%83%8E%E3%83%BC%E3%83%88.html"
+        );
+        assert_eq!(
+            percent_decode(&percent_encode("/a b/ノート.html")),
+            "/a b/ノート.html"
+        );
+    }
+
+    #[test]
+    fn injects_the_reload_script() {
+        let vault = testing::init();
+        let html = render(&vault.join("Linked.md"), "/Linked.html").unwrap();
+        assert!(html.contains("body of linked"));
+        assert!(html.contains("fetch(\"/__stamp/Linked.html\""));
+    }
+
+    #[test]
+    fn serves_assets_in_the_vault() {
+        let root = testing::init();
+        assert!(asset_path(root, "pic.png").is_some());
+        assert!(asset_path(root, "attachments/pic.png").is_some());
+    }
+
+    #[test]
+    fn rejects_assets_outside_the_vault() {
+        let root = testing::init();
+        for target in [
+            "/assets/../../../../etc/hostname",
+            "/assets/..%2F..%2F..%2F..%2Fetc%2Fhostname",
+            "/assets/%2Fetc%2Fhostname",
+        ] {
+            let path = percent_decode(target);
+            let name = path.strip_prefix("/assets/").unwrap();
+            assert_eq!(asset_path(root, name), None, "{target}");
+        }
+    }
+
+    #[test]
+    fn rejects_notes_outside_the_vault() {
+        let root = testing::init();
+        assert!(source_path(root, "/Linked.html").is_some());
+        assert_eq!(source_path(root, "/../vault/Linked.html"), None);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use crate::{build::write_file, diagnostics, link, metadata::tag_url, template, util::escape_html};
+
+// タグごとのノートの一覧。vault全体の変換時にタグのページを作るのに使う
+#[derive(Default)]
+pub struct TagIndex {
+    notes: BTreeMap<String, Vec<(String, String)>>, // タグ -> (タイトル, URL)
+}
+
+impl TagIndex {
+    // #atcoder/abc のような階層のあるタグは親のタグ(atcoder)のページにも載せる
+    pub fn add(&mut self, title: &str, url: &str, tags: &[String]) {
+        for tag in tags {
+            let segments = tag.split('/').collect_vec();
+            for depth in 1..=segments.len() {
+                let notes = self.notes.entry(segments[..depth].join("/")).or_default();
+                if !notes.iter().any(|(_, u)| u == url) {
+                    notes.push((title.to_string(), url.to_string()));
+                }
+            }
+        }
+    }
+
+    // タグごとのページと、すべてのタグの一覧のページ(tags/index.html)を書き出す
+    pub fn write_pages(&self, output: &Path) -> io::Result<()> {
+        let index_path = output.join("tags").join("index.html");
+        for (tag, notes) in self.notes.iter() {
+            // ARMP_TAG_URLによっては一覧のページや出力先の外を指すことがあるので、その場合は書き出さない
+            let Some(path) = tag_page_path(output, tag).filter(|path| *path != index_path) else {
+                diagnostics::warn(format!(
+                    "cannot write the page of tag `{tag}` to the path of its URL `{}`",
+                    tag_url(tag)
+                ));
+                continue;
+            };
+            let items = notes
+                .iter()
+                .sorted()
+                .map(|(title, url)| {
+                    format!(
+                        "<li><a href=\"{}\">{}</a></li>",
+                        escape_html(url),
+                        escape_html(title)
+                    )
+                })
+                .join("\n");
+            let content = format!(
+                "<h2>#{}</h2>\n<ul class=\"tag-notes\">\n{items}\n</ul>",
+                escape_html(tag)
+            );
+            write_file(&path, &page(&format!("#{tag}"), content))?;
+        }
+
+        let items = self
+            .notes
+            .iter()
+            .map(|(tag, notes)| {
+                format!(
+                    "<li><a class=\"tag\" href=\"{}\">#{}</a> ({})</li>",
+                    escape_html(&tag_url(tag)),
+                    escape_html(tag),
+                    notes.len()
+                )
+            })
+            .join("\n");
+        let content = format!("<h2>Tags</h2>\n<ul class=\"tag-index\">\n{items}\n</ul>");
+        write_file(&index_path, &page("Tags", content))
+    }
+}
+
+// ノートのページと同じテンプレートを使う。backlinksやdescriptionなどのノートにしかない変数は空になる
+fn page(title: &str, content: String) -> String {
+    match template::get() {
+        Some(page) => {
+            template::render(page, &[("title", escape_html(title)), ("content", content)])
+        }
+        None => content,
+    }
+}
+
+// タグのページのURLのパス部分を出力先のパスとして使う。..などで出力先の外を指す場合はNone
+fn tag_page_path(output: &Path, tag: &str) -> Option<PathBuf> {
+    let url = tag_url(tag);
+    let path = link::url_path(&url).trim_start_matches('/');
+    if Path::new(path)
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    if path.is_empty() || path.ends_with('/') {
+        Some(output.join(path).join("index.html"))
+    } else {
+        Some(output.join(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn writes_each_tag_page_to_its_own_directory() {
+        testing::init();
+        let output = testing::output_dir("tags");
+        let mut index = TagIndex::default();
+        index.add(
+            "Note",
+            "/Note.html",
+            &["index".to_string(), "a/b".to_string()],
+        );
+        index.write_pages(&output).unwrap();
+        for page in ["index/index.html", "a/index.html", "a/b/index.html"] {
+            assert!(output.join("tags").join(page).is_file(), "{page}");
+        }
+        let list = std::fs::read_to_string(output.join("tags/index.html")).unwrap();
+        assert!(list.contains("<h2>Tags</h2>"));
+        let _ = std::fs::remove_dir_all(output);
+    }
+
+    #[test]
+    fn lists_notes_under_parent_tags() {
+        testing::init();
+        let output = testing::output_dir("tag-pages");
+        let mut index = TagIndex::default();
+        index.add("ABC", "/abc.html", &["atcoder/abc".to_string()]);
+        index.add("ARC", "/arc.html", &["atcoder/arc".to_string()]);
+        index.write_pages(&output).unwrap();
+
+        let parent = std::fs::read_to_string(output.join("tags/atcoder/index.html")).unwrap();
+        assert!(parent.contains("<li><a href=\"/abc.html\">ABC</a></li>"));
+        assert!(parent.contains("<li><a href=\"/arc.html\">ARC</a></li>"));
+        assert!(output.join("tags/atcoder/abc/index.html").is_file());
+        let tags = std::fs::read_to_string(output.join("tags/index.html")).unwrap();
+        assert!(tags.contains(">#atcoder</a> (2)</li>"));
+        let _ = std::fs::remove_dir_all(output);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fs, sync::OnceLock};
+
+use crate::config;
+
+static TEMPLATE: OnceLock<Option<String>> = OnceLock::new();
+
+// 設定されたページのテンプレート。設定されていない場合は変換結果をそのまま出力する
+pub fn get() -> Option<&'static str> {
+    TEMPLATE
+        .get_or_init(|| {
+            let path = config::get().template.as_ref()?;
+            match fs::read_to_string(path) {
+                Ok(template) => Some(template),
+                Err(e) => {
+                    eprintln!("Warning: cannot read template `{}`: {e}", path.display());
+                    None
+                }
+            }
+        })
+        .as_deref()
+}
+
+pub fn uses(variable: &str) -> bool {
+    get().is_some_and(|template| template.contains(&format!("{{{{{variable}}}}}")))
+}
+
+// {{name}} をvariablesの値で置き換える。variablesにない名前は空文字列にする
+// テンプレートを先頭から1度だけ読むので、置き換えた値の中の{{...}}は置き換えない
+pub fn render(template: &str, variables: &[(&str, String)]) -> String {
+    let mut html = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        html.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            let name = &after[..end];
+            if !is_variable_name(name) {
+                return None;
+            }
+            let value = variables
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map_or("", |(_, value)| value.as_str());
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                html.push_str(value);
+                rest = &after[end + 2..];
+            }
+            // {{{title}}}のような場合に備えて1文字だけ進める
+            None => {
+                html.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    html.push_str(rest);
+    html
+}
+
+// {{ }}の中が変数の名前として読めるか。それ以外の{{...}}はそのまま出力する
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn does_not_expand_variables_in_values() {
+        let page = "<title>{{title}}</title>{{content}}";
+        let variables = [
+            ("title", "{{content}}".to_string()),
+            ("content", "<p>{{title}}</p>".to_string()),
+        ];
+        assert_eq!(
+            render(page, &variables),
+            "<title>{{content}}</title><p>{{title}}</p>"
+        );
+        assert_eq!(render("{{{title}}}", &variables), "{{{content}}}");
+    }
+
+    #[test]
+    fn leaves_unknown_variables_empty() {
+        let page = "<meta content=\"{{description}}\">{{content}} {{ not a variable }}";
+        assert_eq!(
+            render(page, &[("content", "body".to_string())]),
+            "<meta content=\"\">body {{ not a variable }}"
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::Once,
+};
+
+use crate::{
+    config::{self, Config},
+    util::set_vault_root,
+};
+
+static INIT: Once = Once::new();
+
+// テスト用のvault(test/vault)と設定を登録する。vaultと設定はプロセス全体で共有されるので、
+// vaultや設定を使うテストは最初にこれを呼ぶ
+pub fn init() -> &'static Path {
+    INIT.call_once(|| {
+        let mut config = Config::default();
+        config
+            .set("attachment_folder", "attachments")
+            .expect("valid config");
+        let _ = config::init(config);
+        let _ = set_vault_root(vault().to_path_buf());
+    });
+    vault()
+}
+
+pub fn vault() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test/vault"))
+}
+
+// テストごとの出力先。前回の実行の結果が残っていれば消す
+pub fn output_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("armp-test-{}-{name}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
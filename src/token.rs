@@ -1,169 +1,219 @@
-use itertools::Itertools;
+use std::collections::HashMap;
 
-use crate::lexer::inline_lexer::InlineLexer;
+// ソース中の位置。offsetはバイト単位、lineとcolumnは1始まりで、columnは文字単位
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
 
-#[derive(Clone, Copy, Debug)]
-pub enum InlineType {
-    Text,
-    Bold,
-    Code,
-    LineBreak,
-    Url,
-    FootNote,
-    Latex,
-    Picture,
+// [start, end) の範囲
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum BlockType {
-    h1,
-    h2,
-    h3,
-    Plain,
-    Empty, // 段落替え
-    Hr,    // 区切り線
-    CodeBlock,
-    Quote, // 引用
-    FootNote,
-    Latex,
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Inline {
+    Text(String),
+    Bold(Vec<InlineToken>),
+    Code(String),
+    LineBreak,
+    // [text](url "title")、参照リンク、<url>や文中のURLによる自動リンク
+    Url {
+        url: String,
+        title: Option<String>,
+        children: Vec<InlineToken>,
+        autolink: bool,
+    },
+    // [[Note#Section|alias]]。解決できなかった場合はurlがNoneになり、displayだけを表示する
+    // 文中の![[Note]]は展開できないのでembedをtrueにしてリンクとして扱う
+    WikiLink {
+        note: String,
+        section: Option<String>,
+        alias: Option<String>,
+        url: Option<String>,
+        display: String,
+        embed: bool,
+    },
+    FootNote {
+        id: String,
+    },
+    Latex {
+        tex: String,
+        display: bool,
+    },
+    // ![alt](src "title") と ![[file|alt|300x200]]。srcは解決済みのURL、targetは書かれたままのパス
+    Picture {
+        target: String,
+        src: String,
+        alt: String,
+        title: Option<String>,
+        width: Option<u32>,
+        height: Option<u32>,
+        wikilink: bool,
+    },
+    // \eqref{label} と [[#label]]。番号は文書全体を読んだ後に振る
+    EqRef {
+        label: String,
+        number: Option<usize>,
+        wikilink: bool,
+    },
+    Tag(String),
 }
 
 #[derive(Clone, Debug)]
 pub struct InlineToken {
-    inline_type: InlineType,
-    text: Option<String>,
-    children: Vec<InlineToken>,
+    pub kind: Inline,
+    pub span: Span,
 }
 
 impl InlineToken {
-    pub fn new(
-        inline_type: InlineType,
-        text: Option<String>,
-        children: Option<Vec<InlineToken>>,
-    ) -> Self {
-        let children = children.unwrap_or_default();
-
-        InlineToken {
-            inline_type,
-            text,
-            children,
-        }
+    pub fn new(kind: Inline, span: Span) -> Self {
+        Self { kind, span }
     }
 
-    pub fn to_html(&self) -> String {
-        match self.inline_type {
-            InlineType::Text => {
-                assert!(self.text.is_some());
-                self.text.clone().unwrap()
-            }
-            InlineType::Bold => {
-                let children_html = self.children.iter().map(|elm| elm.to_html()).join("");
-                format!("<strong>{}</strong>", children_html)
-            }
-            InlineType::LineBreak => "<br>".to_string(),
-            InlineType::Code => {
-                assert!(self.text.is_some());
-                format!(
-                    "<code class=\"inline-code\">{}</code>",
-                    self.text.as_ref().unwrap()
-                )
+    // 子要素も含めて順に訪れる
+    pub fn walk(&self, f: &mut impl FnMut(&InlineToken)) {
+        f(self);
+        match &self.kind {
+            Inline::Bold(children) | Inline::Url { children, .. } => {
+                children.iter().for_each(|child| child.walk(f))
             }
-            InlineType::Url => {
-                assert!(!self.children.is_empty());
-                assert!(self.children[0].text.is_some());
-                assert!(self.text.is_some());
-                let content = self.text.clone().unwrap();
-                let url = self.children[0].text.as_ref().unwrap();
-                format!("<a href=\"{url}\">{content}</a>")
-            }
-            InlineType::FootNote => {
-                assert!(self.text.is_some());
-                let id = self.text.as_ref().unwrap();
-                format!("<span id=\"{id}\"></span>")
-            }
-            InlineType::Latex => {
-                assert!(self.text.is_some());
-                format!("\\({}\\)", self.text.as_ref().unwrap())
-            }
-            InlineType::Picture => {
-                assert!(self.text.is_some());
-                format!(
-                    "<img src=/assets/pictures/{} />",
-                    self.text.as_ref().unwrap()
-                )
+            _ => {}
+        }
+    }
+
+    pub fn walk_mut(&mut self, f: &mut impl FnMut(&mut InlineToken)) {
+        f(self);
+        match &mut self.kind {
+            Inline::Bold(children) | Inline::Url { children, .. } => {
+                children.iter_mut().for_each(|child| child.walk_mut(f))
             }
+            _ => {}
         }
     }
 }
 
+#[derive(Clone, Debug)]
+pub enum Block {
+    // levelは # の数(1〜3)
+    Heading {
+        level: usize,
+        content: Vec<InlineToken>,
+    },
+    Plain {
+        content: Vec<InlineToken>,
+    },
+    Empty, // 段落替え
+    Hr,    // 区切り線
+    // infoは```の後ろに書かれた文字列全体で、langはその最初の単語
+    CodeBlock {
+        lang: Option<String>,
+        info: String,
+        code: String,
+    },
+    // 引用
+    Quote {
+        content: Vec<InlineToken>,
+    },
+    FootNote {
+        id: String,
+        content: Vec<InlineToken>,
+    },
+    // \label{...}は取り除いてlabelに入れる。番号は文書全体を読んだ後に振る
+    Latex {
+        tex: String,
+        label: Option<String>,
+        number: Option<usize>,
+    },
+    // ![[Note]] によるノートの埋め込み
+    Embed {
+        target: String,
+        children: Vec<BlockToken>,
+    },
+    TaskList {
+        items: Vec<TaskItem>,
+    },
+}
+
+// - [ ] によるタスク。statusは[ ]の中の文字
+#[derive(Clone, Debug)]
+pub struct TaskItem {
+    pub status: char,
+    pub content: Vec<InlineToken>,
+    pub span: Span,
+}
+
 #[derive(Clone, Debug)]
 pub struct BlockToken {
-    block_type: BlockType,
-    inline_tokens: Vec<InlineToken>,
+    pub kind: Block,
+    pub span: Span,
 }
 
 impl BlockToken {
-    pub fn new(block_type: BlockType) -> Self {
-        Self {
-            block_type,
-            inline_tokens: Vec::new(),
-        }
+    pub fn new(kind: Block, span: Span) -> Self {
+        Self { kind, span }
     }
 
-    pub fn is_same_type(&self, other: BlockType) -> bool {
-        self.block_type == other
+    // このブロックに含まれるインラインの要素を順に訪れる。埋め込んだノートの中は含めない
+    pub fn walk_inlines(&self, f: &mut impl FnMut(&InlineToken)) {
+        match &self.kind {
+            Block::Heading { content, .. }
+            | Block::Plain { content }
+            | Block::Quote { content }
+            | Block::FootNote { content, .. } => content.iter().for_each(|tk| tk.walk(f)),
+            Block::TaskList { items } => items
+                .iter()
+                .flat_map(|item| item.content.iter())
+                .for_each(|tk| tk.walk(f)),
+            _ => {}
+        }
     }
 
-    pub fn proceed_block_content(&mut self, content: String) {
-        if !self.inline_tokens.is_empty() {
-            self.inline_tokens
-                .push(InlineToken::new(InlineType::LineBreak, None, None));
+    pub fn walk_inlines_mut(&mut self, f: &mut impl FnMut(&mut InlineToken)) {
+        match &mut self.kind {
+            Block::Heading { content, .. }
+            | Block::Plain { content }
+            | Block::Quote { content }
+            | Block::FootNote { content, .. } => content.iter_mut().for_each(|tk| tk.walk_mut(f)),
+            Block::TaskList { items } => items
+                .iter_mut()
+                .flat_map(|item| item.content.iter_mut())
+                .for_each(|tk| tk.walk_mut(f)),
+            _ => {}
         }
-        self.inline_tokens = [
-            self.inline_tokens.clone(),
-            InlineLexer::new(content.chars().collect()).tokenize(),
-        ]
-        .iter()
-        .flatten()
-        .cloned()
-        .collect();
     }
 
-    pub fn process_block_content_as_plain_text(&mut self, content: String) {
-        self.inline_tokens
-            .push(InlineToken::new(InlineType::Text, Some(content), None));
+    pub fn resolve_equation_refs(&mut self, numbers: &HashMap<String, usize>) {
+        self.walk_inlines_mut(&mut |token| {
+            if let Inline::EqRef { label, number, .. } = &mut token.kind {
+                *number = numbers.get(label).copied();
+            }
+        });
     }
 
-    pub fn to_html(&self) -> String {
-        let content = self.inline_tokens.iter().map(|it| it.to_html()).join("\n");
-        match self.block_type {
-            BlockType::h1 => format!("<h2>{content}</h2>"),
-            BlockType::h2 => format!("<h3>{content}</h3>"),
-            BlockType::h3 => format!("<h4>{content}</h4>"),
-            BlockType::Plain => format!("<p>{content}</p>"),
-            BlockType::Empty => "<br>".to_string(),
-            BlockType::Hr => "<hr>".to_string(),
-            BlockType::CodeBlock => {
-                assert_eq!(self.inline_tokens.len(), 2);
-                let content = self.inline_tokens[0].to_html()
-                    .replace("<", "&lt;")
-                    .replace(">", "&gt;");
-                let language = self.inline_tokens[1].to_html();
-
-                format!("<pre><code class=\"codeblock language-{language}\">{content}</code></pre>")
-            }
-            BlockType::Quote => format!("<blockquote>{content}</blockquote>"),
-            BlockType::FootNote => {
-                assert!(self.inline_tokens.len() >= 3);
-                let id = self.inline_tokens[0].text.clone().unwrap();
-                let text = self.inline_tokens[2..]
-                    .iter()
-                    .map(|tk| tk.to_html())
-                    .join("");
-                format!("<foot-note for=\"{id}\">{text}</foot-note>")
+    // 埋め込んだノートのタグは含めない
+    pub fn collect_tags(&self, tags: &mut Vec<String>) {
+        self.walk_inlines(&mut |token| {
+            if let Inline::Tag(tag) = &token.kind {
+                tags.push(tag.clone());
             }
-            BlockType::Latex => format!("\\[{content}\\]"),
+        });
+    }
+
+    // タスクの状態([ ]の中の文字)を集める。埋め込んだノートのタスクは含めない
+    pub fn collect_task_statuses(&self, statuses: &mut Vec<char>) {
+        if let Block::TaskList { items } = &self.kind {
+            statuses.extend(items.iter().map(|item| item.status));
         }
     }
 }
@@ -1,22 +1,97 @@
-use std::{env, path::PathBuf};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use walkdir::WalkDir;
 
-pub fn get_path(filename: String) -> Option<PathBuf> {
-    let repo_root = match env::var("KNOWLEDGES") {
-        Ok(path) => PathBuf::from(path),
-        Err(_) => {
-            unreachable!(); /* 起動時に確認済み */
-        }
-    };
+static VAULT_ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
 
-    let target = format!("{filename}.md");
+// --vaultなどで指定されたvault。最初にvault_root()が呼ばれるより前に呼ぶ必要がある
+pub fn set_vault_root(path: PathBuf) -> Result<(), Option<PathBuf>> {
+    VAULT_ROOT.set(Some(path))
+}
+
+// vaultのディレクトリ。指定されていなければ環境変数KNOWLEDGESを使う
+// どちらもない場合はNoneで、内部リンクや画像はvaultから探さずに解決できないものとして扱う
+pub fn vault_root() -> Option<&'static Path> {
+    VAULT_ROOT
+        .get_or_init(|| env::var_os("KNOWLEDGES").map(PathBuf::from))
+        .as_deref()
+}
 
-    for entry in WalkDir::new(repo_root).into_iter().filter_map(|e| e.ok()) {
+pub fn get_path(filename: String) -> Option<PathBuf> {
+    find_file(&format!("{filename}.md"))
+}
+
+// vault内から末尾が一致するファイルを探す。"folder/image.png"のような指定もできる
+// vaultからの相対パスとして一致するものがあればそれを優先する
+pub fn find_file(relative: &str) -> Option<PathBuf> {
+    let target = Path::new(relative);
+    let exact = vault_root()?.join(target);
+    if target.is_relative() && exact.is_file() {
+        return Some(exact);
+    }
+    for entry in WalkDir::new(vault_root()?)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
         let path = entry.path();
-        if path.is_file() && path.file_name().map_or(false, |f| f == target.as_str()) {
+        if path.is_file() && path.ends_with(target) {
             return Some(path.to_path_buf());
         }
     }
     None
 }
+
+// vault内のノートの一覧。.obsidianなどの隠しディレクトリは除く
+pub fn vault_notes() -> Vec<PathBuf> {
+    let Some(root) = vault_root() else {
+        return vec![];
+    };
+    WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "md"))
+        .collect()
+}
+
+// 出力ファイル名に使う内容のハッシュ(FNV-1a)。実行環境によらず同じ値になる
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+// HTMLのテキストや属性値に埋め込むためのエスケープ
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// JSONの文字列リテラルとして出力する
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
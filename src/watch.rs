@@ -0,0 +1,263 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use itertools::Itertools;
+
+use crate::{
+    build::{self, BuiltNote},
+    diagnostics,
+    graph::{self, LinkGraph},
+    resolver,
+    token::{Block, BlockToken},
+    util::vault_notes,
+};
+
+// vaultを調べる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// ファイルの更新日時と大きさ。どちらかが変わったら変更されたとみなす
+type Stamp = (Option<SystemTime>, u64);
+
+// 変更を検出して影響のあるノートだけを変換し直すための状態
+struct Watcher {
+    output: PathBuf,
+    graph: LinkGraph,
+    stamps: BTreeMap<PathBuf, Stamp>,
+    // 以下はノートのID(vaultからの相対パス)をキーにする
+    notes: BTreeMap<String, BuiltNote>,
+    urls: BTreeMap<String, Option<String>>, // ノートの公開先のURL(<!-- url: -->)
+    embeds: BTreeMap<String, BTreeSet<String>>, // ノート -> 埋め込んでいるノート(入れ子になったものも含む)
+}
+
+// vault全体を変換した後、vaultを定期的に調べて変更のあったノートを変換し直す
+// inotifyなどは使わずにポーリングするので、どの環境でも同じように動く
+// 読み書きに失敗したノートは警告を出して飛ばし、監視は続ける
+pub fn watch(output: &Path) -> io::Result<()> {
+    let mut watcher = Watcher::new(output);
+    eprintln!("Watching {} notes", watcher.notes.len());
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let rebuilt = watcher.poll();
+        if !rebuilt.is_empty() {
+            eprintln!("Rebuilt: {}", rebuilt.iter().join(", "));
+        }
+    }
+}
+
+impl Watcher {
+    fn new(output: &Path) -> Self {
+        let graph = LinkGraph::from_vault();
+        let mut watcher = Self {
+            output: output.to_path_buf(),
+            stamps: scan(),
+            graph,
+            notes: BTreeMap::new(),
+            urls: BTreeMap::new(),
+            embeds: BTreeMap::new(),
+        };
+        let ids = watcher
+            .graph
+            .notes()
+            .map(|(id, _)| id.clone())
+            .collect_vec();
+        for id in ids {
+            watcher.rebuild(&id);
+        }
+        watcher.write_indexes();
+        watcher
+    }
+
+    // 変更があれば変換し直し、変換したノートの名前を返す
+    fn poll(&mut self) -> Vec<String> {
+        let stamps = scan();
+        if stamps == self.stamps {
+            return vec![];
+        }
+        let changed: BTreeSet<String> = stamps
+            .iter()
+            .filter(|(path, stamp)| self.stamps.get(*path) != Some(stamp))
+            .map(|(path, _)| graph::note_id(path))
+            .collect();
+        let removed: BTreeMap<String, PathBuf> = self
+            .stamps
+            .keys()
+            .filter(|path| !stamps.contains_key(*path))
+            .map(|path| (graph::note_id(path), path.clone()))
+            .collect();
+        self.stamps = stamps;
+
+        let graph = LinkGraph::from_vault();
+        let mut targets = BTreeSet::new();
+        for name in changed.iter().chain(removed.keys()) {
+            targets.insert(name.clone());
+            // 埋め込んでいるノート
+            targets.extend(
+                self.embeds
+                    .iter()
+                    .filter(|(_, embeds)| embeds.contains(name))
+                    .map(|(source, _)| source.clone()),
+            );
+            // URLが変わった、または追加・削除されたノートにリンクしているノート
+            let url = resolver::get().resolve_note(name).map(|link| link.url);
+            let added = !self.notes.contains_key(name);
+            if added || removed.contains_key(name) || self.urls.get(name) != Some(&url) {
+                targets.extend(self.graph.backlinks(name).into_iter().cloned());
+                targets.extend(graph.backlinks(name).into_iter().cloned());
+                // リンク先のノートはバックリンクにこのノートのURLを載せている
+                targets.extend(self.graph.links_from(name).cloned());
+                targets.extend(graph.links_from(name).cloned());
+            }
+            // リンクを追加・削除されたノートはバックリンクが変わる
+            let before: BTreeSet<&String> = self.graph.links_from(name).collect();
+            let after: BTreeSet<&String> = graph.links_from(name).collect();
+            targets.extend(before.symmetric_difference(&after).map(|s| s.to_string()));
+        }
+        self.graph = graph;
+
+        for (name, path) in removed.iter() {
+            self.remove(name, path);
+        }
+        let mut rebuilt = vec![];
+        for name in targets {
+            if self.rebuild(&name) {
+                rebuilt.push(name);
+            }
+        }
+        self.write_indexes();
+        rebuilt
+    }
+
+    // vaultにないノートや変換できなかったノートの場合はfalseを返す
+    // 変換できなかった場合は前回の変換結果を残す
+    fn rebuild(&mut self, name: &str) -> bool {
+        let Some(path) = self
+            .graph
+            .notes()
+            .find(|(n, _)| *n == name)
+            .map(|(_, path)| path.clone())
+        else {
+            return false;
+        };
+        let note = match build::build_note(&self.output, &path, &self.graph) {
+            Ok(note) => note,
+            Err(e) => {
+                diagnostics::warn(format!("cannot build `{}`: {e}", path.display()));
+                return false;
+            }
+        };
+        let mut embeds = BTreeSet::new();
+        collect_embeds(&note.tokens, &mut embeds);
+        // 埋め込みは[[Name]]の形で書かれているので、変更の検出に使うIDにする
+        let embeds = embeds
+            .iter()
+            .filter_map(|embed| self.graph.resolve(embed).cloned())
+            .collect();
+        self.embeds.insert(name.to_string(), embeds);
+        self.urls.insert(
+            name.to_string(),
+            resolver::get().resolve_note(name).map(|link| link.url),
+        );
+        self.notes.insert(name.to_string(), note);
+        true
+    }
+
+    fn write_indexes(&self) {
+        if let Err(e) = build::write_indexes(&self.output, &self.graph, self.notes.values()) {
+            diagnostics::warn(format!("cannot write the indexes: {e}"));
+        }
+    }
+
+    // 削除されたノートの出力を消す。別のディレクトリに移動された場合は移動先で変換し直される
+    fn remove(&mut self, name: &str, path: &Path) {
+        let _ = fs::remove_file(build::output_path(&self.output, path));
+        if self.graph.notes().all(|(n, _)| n != name) {
+            self.notes.remove(name);
+            self.urls.remove(name);
+            self.embeds.remove(name);
+        }
+    }
+}
+
+fn scan() -> BTreeMap<PathBuf, Stamp> {
+    vault_notes()
+        .into_iter()
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok()?;
+            Some((path, (metadata.modified().ok(), metadata.len())))
+        })
+        .collect()
+}
+
+fn collect_embeds(blocks: &[BlockToken], embeds: &mut BTreeSet<String>) {
+    for block in blocks {
+        if let Block::Embed { target, children } = &block.kind {
+            let note = target.split(['#', '|']).next().unwrap_or_default().trim();
+            embeds.insert(note.to_string());
+            collect_embeds(children, embeds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn rebuilds_notes_that_link_to_a_changed_url() {
+        testing::init();
+        let output = testing::output_dir("watch");
+        let mut watcher = Watcher::new(&output);
+        assert!(output.join("Linked.html").is_file());
+        assert_eq!(watcher.poll(), Vec::<String>::new());
+
+        // Linked.mdが更新され、公開先のURLも変わったことにする
+        let linked = watcher
+            .stamps
+            .keys()
+            .find(|path| path.ends_with("Linked.md"))
+            .unwrap()
+            .clone();
+        watcher.stamps.insert(linked, (None, 0));
+        watcher.urls.insert("Linked".to_string(), None);
+        assert_eq!(watcher.poll(), ["Linked", "a/README"]);
+
+        // URLが変わったa/READMEのリンク先のLinkedもバックリンクを更新する
+        let readme = watcher
+            .stamps
+            .keys()
+            .find(|path| path.ends_with("a/README.md"))
+            .unwrap()
+            .clone();
+        watcher.stamps.insert(readme, (None, 0));
+        watcher
+            .urls
+            .insert("a/README".to_string(), Some("/old.html".to_string()));
+        assert_eq!(watcher.poll(), ["Linked", "a/README"]);
+        let _ = fs::remove_dir_all(output);
+    }
+
+    #[test]
+    fn keeps_watching_after_errors() {
+        testing::init();
+        // 出力先がファイルなのでどのノートも書き出せない
+        let output = testing::output_dir("watch-errors");
+        fs::write(&output, "").unwrap();
+        let (mut watcher, diagnostics) = diagnostics::collect(|| Watcher::new(&output));
+        assert!(watcher.notes.is_empty());
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.message.starts_with("cannot build")));
+        assert!(watcher
+            .stamps
+            .keys()
+            .any(|path| path.ends_with("a/README.md")));
+        assert_eq!(watcher.poll(), Vec::<String>::new());
+        let _ = fs::remove_file(output);
+    }
+}